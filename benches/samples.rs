@@ -0,0 +1,33 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 10;
+
+fn bench(sample: &str) {
+    let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples");
+    let mut times = Vec::with_capacity(RUNS);
+
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let status = Command::new(env!("CARGO_BIN_EXE_advrs"))
+            .args(["run", sample])
+            .current_dir(&samples)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .expect("Failed to start the interpreter");
+        times.push(start.elapsed());
+        assert!(status.success(), "Running {sample} failed");
+    }
+
+    times.sort();
+    let mean = times.iter().sum::<Duration>() / RUNS as u32;
+    println!("{sample:<24} min {:>10.2?}  median {:>10.2?}  mean {:>10.2?}", times[0], times[RUNS / 2], mean);
+}
+
+fn main() {
+    bench("fibonacci.adv");
+    bench("primegen.adv");
+}
@@ -0,0 +1,130 @@
+use std::io::{self, prelude::*};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::symbol::*;
+use crate::error::*;
+use crate::class_table::*;
+use crate::opcode::*;
+use crate::interpreter::*;
+use crate::gc::*;
+
+// Gets the receiver and the arguments, as many as the arity it was registered with
+pub type Builtin = Box<dyn Fn(&RunCtx, &mut GC, Object, &[Object]) -> Result<Object, RuntimeErrorKind>>;
+
+// Where the char builtins read lines from and write to, `None` stands for the stdin or stdout of the process
+#[derive(Default)]
+pub struct Stdio {
+    pub input: Option<Box<dyn BufRead>>,
+    pub output: Option<Box<dyn Write>>,
+}
+
+// Native implementations of methods declared without a body, a builtin registered for a class is inherited by its subclasses
+#[derive(Default)]
+pub struct Builtins {
+    methods: HashMap<(Symbol, Symbol), (usize, Builtin)>,
+}
+
+impl Builtins {
+    pub fn register(&mut self, class: Symbol, method: Symbol, arity: usize, builtin: impl Fn(&RunCtx, &mut GC, Object, &[Object]) -> Result<Object, RuntimeErrorKind> + 'static) -> &mut Self {
+        self.methods.insert((class, method), (arity, Box::new(builtin)));
+        self
+    }
+
+    // Registrations of `other` replace the ones for the same methods
+    pub fn merge(&mut self, other: Builtins) {
+        self.methods.extend(other.methods);
+    }
+
+    // The one registered for the closest ancestor of `class` (including itself)
+    pub fn get(&self, class_table: &ClassTable, class: usize, method: Symbol) -> Option<&Builtin> {
+        self.find(class_table, class, method).map(|(_, builtin)| builtin)
+    }
+
+    fn find(&self, class_table: &ClassTable, class: usize, method: Symbol) -> Option<&(usize, Builtin)> {
+        let mut class = Some(&class_table.classes[class]);
+        while let Some(c) = class {
+            if let Some(builtin) = self.methods.get(&(c.name, method)) {
+                return Some(builtin);
+            }
+            class = c.parent.map(|p| &class_table.classes[class_table.map[&p].0]);
+        }
+        None
+    }
+
+    // Every method without a body that some builtin would run for has to take as many arguments as that builtin
+    pub fn check(&self, class_table: &ClassTable, classes: &[CompiledClass]) -> Result<()> {
+        for (id, class) in classes.iter().enumerate() {
+            for method in class.methods.iter().filter(|m| m.body.is_none()) {
+                if let Some((arity, _)) = self.find(class_table, id, method.name) {
+                    ensure!(*arity == method.params_count, AdvError::BuiltinArity { class: class_table.classes[id].name, method: method.name, declared: method.params_count, expected: *arity });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // `'builtin:push_char'(c)`, `'builtin:pop_char'()`, `'builtin:write'()` and `'builtin:read'()`, which share a stack of characters
+    // Writing prints the whole stack, reading replaces it with a line of stdin that's popped starting from its first character
+    pub fn register_chars(&mut self, class: Symbol, stdio: Stdio) -> &mut Self {
+        let chars = Rc::new(RefCell::new(String::new()));
+        let Stdio { input, output } = stdio;
+        let (input, output) = (RefCell::new(input), RefCell::new(output));
+
+        let stack = chars.clone();
+        self.register(class, "'builtin:push_char'".into(), 1, move |ctx, gc, _, args| {
+            let class_name = args[0].class_name(&ctx.class_table);
+            let char = match class_name.strip_prefix('\'').and_then(|c| c.strip_suffix('\'')) {
+                Some("\\n") => '\n',
+                Some("\\'") => '\'',
+                Some("\\0") => '\0',
+                Some("\\\\") => '\\',
+                Some(c) if c.chars().count() == 1 => c.chars().next().unwrap(),
+                _ => bail!(RuntimeErrorKind::NotACharacter { class: ctx.class_table.classes[args[0].class()].name }),
+            };
+            stack.borrow_mut().push(char);
+            Object::null(ctx, gc)
+        });
+
+        let stack = chars.clone();
+        self.register(class, "'builtin:pop_char'".into(), 0, move |ctx, gc, _, _| {
+            let char = stack.borrow_mut().pop().unwrap_or('\0');
+            let char_name = match char {
+                '\n' => "\\n",
+                '\'' => "\\'",
+                '\0' => "\\0",
+                '\\' => "\\\\",
+                c => &c.to_string()
+            };
+            if let Ok(class) = ctx.class_table.get_class_id(format!("'{char_name}'").as_str().into()) {
+                Object::new(ctx, gc, class)
+            } else {
+                Object::null(ctx, gc)
+            }
+        });
+
+        let stack = chars.clone();
+        self.register(class, "'builtin:write'".into(), 0, move |ctx, gc, _, _| {
+            let mut stack = stack.borrow_mut();
+            match &mut *output.borrow_mut() {
+                Some(output) => output.write_all(stack.as_bytes()),
+                None => io::stdout().write_all(stack.as_bytes()),
+            }.map_err(RuntimeErrorKind::Io)?;
+            stack.clear();
+            Object::null(ctx, gc)
+        });
+
+        self.register(class, "'builtin:read'".into(), 0, move |ctx, gc, _, _| {
+            let mut inp = String::new();
+            match &mut *input.borrow_mut() {
+                Some(input) => input.read_line(&mut inp),
+                None => io::stdin().read_line(&mut inp),
+            }.map_err(RuntimeErrorKind::Io)?;
+            let mut stack = chars.borrow_mut();
+            stack.clear();
+            stack.extend(inp.chars().rev());
+            Object::null(ctx, gc)
+        })
+    }
+}
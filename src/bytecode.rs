@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::syntax::*;
+use crate::symbol::*;
+use crate::error::*;
+use crate::class_table::*;
+use crate::opcode::*;
+use crate::namespace::*;
+use crate::opcode::OpCode::*;
+
+// Layout of a compiled program:
+//   magic, format version (u32 LE), checksum of the rest of the file (u64 LE)
+//   target version, string table, entrypoints, singletons, selectors, class table, methods, compiled classes
+// Integers are LEB128 encoded and strings are referenced by their index in the string table
+// Methods are stored once and referenced by index, since subclasses share the ones they inherit
+pub const MAGIC: &[u8; 4] = b"ADVC";
+pub const FORMAT_VERSION: u32 = 4;
+
+const HEADER_SIZE: usize = MAGIC.len() + 4 + 8;
+
+fn checksum(bytes: &[u8]) -> u64 { // FNV-1a
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    strings: Vec<String>,
+    string_ids: HashMap<String, usize>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        self.bytes.push(b);
+    }
+
+    fn uint(&mut self, mut n: usize) {
+        loop {
+            let b = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.byte(b);
+                return;
+            }
+            self.byte(b | 0x80);
+        }
+    }
+
+    fn raw_str(&mut self, s: &str) {
+        self.uint(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        let id = if let Some(id) = self.string_ids.get(s) {
+            *id
+        } else {
+            self.strings.push(s.to_owned());
+            self.string_ids.insert(s.to_owned(), self.strings.len() - 1);
+            self.strings.len() - 1
+        };
+        self.uint(id);
+    }
+
+    fn strings<S: AsRef<str>>(&mut self, list: &[S]) {
+        self.uint(list.len());
+        for s in list {
+            self.string(s.as_ref());
+        }
+    }
+
+    fn span(&mut self, span: &Span) {
+        self.string(&span.file);
+        self.uint(span.line);
+        self.uint(span.column);
+    }
+
+    fn opcode(&mut self, op: &OpCode) {
+        match op {
+            New(class) => { self.byte(0); self.uint(*class); },
+            GetV(id) => { self.byte(1); self.uint(*id); },
+            This => self.byte(2),
+            GetF(name, _) => { self.byte(3); self.string(name.as_str()); },
+            GetFI(index) => { self.byte(4); self.uint(*index); },
+            Call(selector, argc, _) => { self.byte(5); self.uint(*selector); self.uint(*argc); },
+            Is(range) => { self.byte(6); self.uint(range.0); self.uint(range.1); },
+            Equals => self.byte(7),
+            SetV(id) => { self.byte(8); self.uint(*id); },
+            SetF(name, _) => { self.byte(9); self.string(name.as_str()); },
+            SetFI(index) => { self.byte(10); self.uint(*index); },
+            Return => self.byte(11),
+            Jump(expected, location) => { self.byte(if *expected { 13 } else { 12 }); self.uint(*location); },
+            Recurse => self.byte(14),
+            Pop => self.byte(15),
+        }
+    }
+
+    fn method(&mut self, method: &CompiledMethod) {
+        self.string(method.name.as_str());
+        self.uint(method.params_count);
+        self.uint(method.locals_size);
+        self.string(method.unit.as_str());
+        self.byte(method.private as u8);
+        if let Some(body) = &method.body {
+            self.byte(1);
+            self.uint(body.len());
+            for op in body {
+                self.opcode(op);
+            }
+            for span in &method.spans {
+                self.span(span);
+            }
+        } else {
+            self.byte(0);
+        }
+    }
+}
+
+pub fn serialize(metadata: &Metadata, class_table: &ClassTable, classes: &[CompiledClass]) -> Vec<u8> {
+    let mut body = Writer::new();
+
+    body.strings(&metadata.entrypoints);
+    body.strings(&metadata.singletons);
+    body.strings(class_table.selectors.names());
+
+    body.uint(class_table.classes.len());
+    for c in &class_table.classes {
+        body.string(c.name.as_str());
+        if let Some(parent) = c.parent {
+            body.byte(1);
+            body.string(parent.as_str());
+        } else {
+            body.byte(0);
+        }
+        body.uint(class_table.map[&c.name].1);
+        body.strings(&c.own_fields);
+        for span in &c.field_spans {
+            body.span(span);
+        }
+        body.strings(&c.private_fields);
+        body.byte(c.private as u8);
+        body.span(&c.span);
+    }
+
+    let mut method_ids = HashMap::new();
+    let mut methods = Vec::new();
+    for m in classes.iter().flat_map(|c| &c.methods) {
+        method_ids.entry(Rc::as_ptr(m)).or_insert_with(|| {
+            methods.push(m);
+            methods.len() - 1
+        });
+    }
+    body.uint(methods.len());
+    for m in methods {
+        body.method(m);
+    }
+
+    for c in classes {
+        body.strings(&c.fields);
+        body.uint(c.methods.len());
+        for m in &c.methods {
+            body.uint(method_ids[&Rc::as_ptr(m)]);
+        }
+    }
+
+    let mut payload = Writer::new();
+    payload.raw_str(&metadata.target);
+    payload.uint(body.strings.len());
+    for s in &body.strings {
+        payload.raw_str(s);
+    }
+    payload.bytes.extend(body.bytes);
+
+    let mut result = Vec::with_capacity(HEADER_SIZE + payload.bytes.len());
+    result.extend_from_slice(MAGIC);
+    result.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    result.extend_from_slice(&checksum(&payload.bytes).to_le_bytes());
+    result.extend(payload.bytes);
+    result
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    strings: Vec<String>,
+    files: Vec<Option<Arc<str>>>, // Shared between spans, created on first use
+}
+
+macro_rules! invalid {
+    ($($arg:tt)*) => {
+        AdvError::Bytecode(format!($($arg)*))
+    };
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self.bytes.get(self.pos).ok_or_else(|| invalid!("Unexpected end of file"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn uint(&mut self) -> Result<usize> {
+        let mut result = 0usize;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            ensure!(shift < usize::BITS && ((b & 0x7f) as usize) << shift >> shift == (b & 0x7f) as usize, invalid!("Integer out of range at byte {}", self.pos - 1));
+            result |= ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => bail!(invalid!("Invalid flag {b} at byte {}", self.pos - 1)),
+        }
+    }
+
+    fn raw_str(&mut self) -> Result<String> {
+        let len = self.uint()?;
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or_else(|| invalid!("Unexpected end of file"))?;
+        let s = std::str::from_utf8(&self.bytes[self.pos..end]).map_err(|_| invalid!("Invalid string at byte {}", self.pos))?;
+        self.pos = end;
+        Ok(s.to_owned())
+    }
+
+    fn string_id(&mut self) -> Result<usize> {
+        let id = self.uint()?;
+        ensure!(id < self.strings.len(), invalid!("String id {id} is out of range"));
+        Ok(id)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let id = self.string_id()?;
+        Ok(self.strings[id].to_owned())
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>> {
+        let count = self.uint()?;
+        (0..count).map(|_| self.string()).collect()
+    }
+
+    fn symbol(&mut self) -> Result<Symbol> {
+        let id = self.string_id()?;
+        Ok(Symbol::intern(&self.strings[id]))
+    }
+
+    fn symbols(&mut self) -> Result<Vec<Symbol>> {
+        let count = self.uint()?;
+        (0..count).map(|_| self.symbol()).collect()
+    }
+
+    fn span(&mut self) -> Result<Span> {
+        let id = self.string_id()?;
+        let file = self.files[id].get_or_insert_with(|| self.strings[id].as_str().into()).clone();
+        Ok(Span {
+            file,
+            line: self.uint()?,
+            column: self.uint()?,
+        })
+    }
+
+    fn opcode(&mut self) -> Result<OpCode> {
+        Ok(match self.byte()? {
+            0 => New(self.uint()?),
+            1 => GetV(self.uint()?),
+            2 => This,
+            3 => GetF(self.symbol()?, 0),
+            4 => GetFI(self.uint()?),
+            5 => Call(self.uint()?, self.uint()?, 0),
+            6 => Is(TypeRange(self.uint()?, self.uint()?)),
+            7 => Equals,
+            8 => SetV(self.uint()?),
+            9 => SetF(self.symbol()?, 0),
+            10 => SetFI(self.uint()?),
+            11 => Return,
+            12 => Jump(false, self.uint()?),
+            13 => Jump(true, self.uint()?),
+            14 => Recurse,
+            15 => Pop,
+            b => bail!(invalid!("Invalid opcode {b} at byte {}", self.pos - 1)),
+        })
+    }
+
+    fn method(&mut self) -> Result<CompiledMethod> {
+        let name = self.symbol()?;
+        let params_count = self.uint()?;
+        let locals_size = self.uint()?;
+        let unit = self.symbol()?;
+        let private = self.bool()?;
+        let (body, spans, sites) = if self.bool()? {
+            let len = self.uint()?;
+            let mut body = (0..len).map(|_| self.opcode()).collect::<Result<Vec<_>>>()?;
+            let spans = (0..len).map(|_| self.span()).collect::<Result<Vec<_>>>()?;
+            let sites = number_cache_sites(&mut body);
+            (Some(body), spans, sites)
+        } else {
+            (None, vec![], 0)
+        };
+
+        Ok(CompiledMethod {
+            name,
+            caches: vec![InlineCache::default(); sites],
+            body,
+            spans,
+            params_count,
+            locals_size,
+            unit,
+            private,
+        })
+    }
+}
+
+pub fn deserialize(bytes: &[u8]) -> Result<(Metadata, ClassTable, Vec<CompiledClass>)> {
+    ensure!(bytes.len() >= HEADER_SIZE && bytes.starts_with(MAGIC), invalid!("Not a compiled adv program"));
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    ensure!(version == FORMAT_VERSION, invalid!("Unsupported bytecode format version {version} (expected {FORMAT_VERSION}), the program has to be recompiled"));
+    let expected_checksum = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+    let payload = &bytes[HEADER_SIZE..];
+    ensure!(checksum(payload) == expected_checksum, invalid!("The file is corrupted (checksum mismatch)"));
+
+    let mut reader = Reader {
+        bytes: payload,
+        pos: 0,
+        strings: vec![],
+        files: vec![],
+    };
+
+    let target = reader.raw_str()?;
+    ensure!(target == CURRENT_VERSION, AdvError::VersionMismatch { target });
+
+    let string_count = reader.uint()?;
+    reader.strings = (0..string_count).map(|_| reader.raw_str()).collect::<Result<_>>()?;
+    reader.files = vec![None; string_count];
+
+    let metadata = Metadata {
+        target,
+        dependencies: vec![],
+        module: None, // Names are already qualified
+        uses: vec![],
+        entrypoints: reader.strings()?,
+        singletons: reader.strings()?,
+    };
+    let selectors = Selectors::from_names(reader.symbols()?)?;
+
+    let class_count = reader.uint()?;
+    let mut classes = Vec::new();
+    let mut map: HashMap<Symbol, TypeRange> = HashMap::new();
+    for i in 0..class_count {
+        let name = reader.symbol()?;
+        let parent = if reader.bool()? { Some(reader.symbol()?) } else { None };
+        let end = reader.uint()?;
+        ensure!(end > i && end <= class_count, invalid!("Class '{name}' has an invalid type range"));
+        if let Some(parent) = &parent {
+            let range = map.get(parent).ok_or_else(|| invalid!("Class '{name}' has an invalid parent '{parent}'"))?;
+            ensure!(range.matches(i) && end <= range.1, invalid!("Class '{name}' is outside of the type range of its parent '{parent}'"));
+        }
+        ensure!(map.insert(name, TypeRange(i, end)).is_none(), invalid!("Class '{name}' is defined multiple times"));
+
+        let own_fields = reader.symbols()?;
+        let field_spans = own_fields.iter().map(|_| reader.span()).collect::<Result<_>>()?;
+        let private_fields = reader.symbols()?;
+        ensure!(private_fields.iter().all(|f| own_fields.contains(f)), invalid!("Class '{name}' has private fields that it doesn't declare"));
+        classes.push(Class {
+            name,
+            module: module_of(name),
+            parent,
+            field_spans,
+            private_fields,
+            own_fields,
+            own_methods: vec![], // Only the compiled versions of methods are stored, they know whether they're private
+            private: reader.bool()?,
+            span: reader.span()?,
+        });
+    }
+    let class_table = ClassTable::from_parts(classes, map, selectors)?;
+
+    let method_count = reader.uint()?;
+    let methods = (0..method_count).map(|_| Ok(Rc::new(reader.method()?))).collect::<Result<Vec<_>>>()?;
+
+    let compiled = (0..class_count).map(|_| {
+        let fields = reader.symbols()?;
+        let count = reader.uint()?;
+        let methods = (0..count).map(|_| {
+            let id = reader.uint()?;
+            Ok(methods.get(id).ok_or_else(|| invalid!("Method id {id} is out of range"))?.clone())
+        }).collect::<Result<Vec<_>>>()?;
+        CompiledClass::new(&class_table, fields, methods)
+    }).collect::<Result<Vec<_>>>()?;
+
+    ensure!(reader.pos == payload.len(), invalid!("Unexpected data at the end of the file"));
+
+    // Objects are laid out by the compiled classes, so they have to agree with what the class table declares
+    for (c, class) in class_table.classes.iter().zip(&compiled) {
+        let inherited = c.parent.as_ref().map_or(&[][..], |p| &compiled[class_table.map[p].0].fields);
+        ensure!(class.fields.len() == inherited.len() + c.own_fields.len() && class.fields.starts_with(inherited) && class.fields.ends_with(&c.own_fields), invalid!("The fields of class '{}' don't match its declaration", c.name));
+    }
+    // The interpreter creates these on its own, and every field of a new `Null` would need yet another `Null`
+    for range in [class_table.null, class_table.truth, class_table.lie] {
+        if range != TypeRange::EMPTY {
+            ensure!(compiled[range.0].fields.is_empty(), invalid!("Class '{}' can't have fields", class_table.classes[range.0].name));
+        }
+    }
+    verify(&class_table, &compiled)?;
+
+    Ok((metadata, class_table, compiled))
+}
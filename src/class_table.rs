@@ -1,8 +1,8 @@
 use std::collections::HashMap;
 
-use anyhow::{Result, Context, ensure};
-
 use crate::syntax::*;
+use crate::symbol::*;
+use crate::error::*;
 
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub struct TypeRange(pub usize, pub usize);
@@ -11,14 +11,222 @@ impl TypeRange {
     pub const EMPTY: Self = Self(0, 0);
 
     pub fn matches(&self, class: usize) -> bool {
-        return class >= self.0 && class < self.1;
+        class >= self.0 && class < self.1
+    }
+}
+
+// Method names get interned into integer selectors, which index the vtables of compiled classes
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Selectors {
+    names: Vec<Symbol>,
+    ids: HashMap<Symbol, usize>,
+}
+
+impl Selectors {
+    pub fn from_names(names: Vec<Symbol>) -> Result<Self> {
+        let mut ids = HashMap::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            ensure!(ids.insert(*name, i).is_none(), AdvError::Bytecode(format!("Selector '{name}' is defined multiple times")));
+        }
+        Ok(Self {
+            names,
+            ids,
+        })
+    }
+
+    pub fn intern(&mut self, name: Symbol) {
+        if !self.ids.contains_key(&name) {
+            self.ids.insert(name, self.names.len());
+            self.names.push(name);
+        }
+    }
+
+    // Interns the name of every method called in `body`, so they can be compiled even if no class defines them yet
+    pub fn intern_calls(&mut self, body: &[Statement]) {
+        fn expr(selectors: &mut Selectors, e: &Expression) {
+            match &e.kind {
+                ExpressionKind::Get(_) => (),
+                ExpressionKind::GetF(obj, _) | ExpressionKind::Is(obj, _) => expr(selectors, obj),
+                ExpressionKind::Call(obj, name, args) => {
+                    expr(selectors, obj);
+                    args.iter().for_each(|a| expr(selectors, a));
+                    selectors.intern(*name);
+                },
+                ExpressionKind::Equals(a, b) => {
+                    expr(selectors, a);
+                    expr(selectors, b);
+                },
+            }
+        }
+
+        for s in body {
+            match &s.kind {
+                StatementKind::SetV(_, value) | StatementKind::Return(value) => expr(self, value),
+                StatementKind::SetF(obj, _, value) => {
+                    expr(self, obj);
+                    expr(self, value);
+                },
+                StatementKind::Call(obj, name, args) => {
+                    expr(self, obj);
+                    args.iter().for_each(|a| expr(self, a));
+                    self.intern(*name);
+                },
+                StatementKind::If(condition, body) | StatementKind::While(condition, body) => {
+                    expr(self, condition);
+                    self.intern_calls(body);
+                },
+            }
+        }
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<usize> {
+        self.ids.get(&name).copied()
+    }
+
+    pub fn name(&self, selector: usize) -> Symbol {
+        self.names[selector]
+    }
+
+    pub fn names(&self) -> &[Symbol] {
+        &self.names
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+// Every method name that's defined or called somewhere gets a selector
+fn collect_selectors(classes: &[Class]) -> Selectors {
+    let mut selectors = Selectors::default();
+    for c in classes {
+        for m in &c.own_methods {
+            selectors.intern(m.name);
+        }
+    }
+    for m in classes.iter().flat_map(|c| &c.own_methods) {
+        if let Some(body) = &m.body {
+            selectors.intern_calls(body);
+        }
+    }
+    selectors
+}
+
+// Classes defined more than once (possibly in different files), and fields or methods defined more than once in the same class
+fn find_duplicates(classes: &[Class]) -> Vec<AdvError> {
+    let mut errors = Vec::new();
+    let mut seen: HashMap<Symbol, &Class> = HashMap::new();
+    for c in classes {
+        if let Some(previous) = seen.insert(c.name, c) {
+            errors.push(AdvError::DuplicateClass { span: c.span.to_owned(), previous: previous.span.to_owned(), class: c.name });
+        }
+
+        for (i, field) in c.own_fields.iter().enumerate() {
+            if let Some(j) = c.own_fields[..i].iter().position(|f| f == field) {
+                errors.push(AdvError::DuplicateField { span: c.field_spans[i].to_owned(), previous: c.field_spans[j].to_owned(), class: c.name, field: *field });
+            }
+        }
+
+        for (i, method) in c.own_methods.iter().enumerate() {
+            if let Some(previous) = c.own_methods[..i].iter().find(|m| m.name == method.name) {
+                errors.push(AdvError::DuplicateMethod { span: method.span.to_owned(), previous: previous.span.to_owned(), class: c.name, method: method.name });
+            }
+        }
+    }
+    errors
+}
+
+// Fields that are already defined by one of the ancestors, `classes` has to be ordered like in the class table
+fn find_shadowed_fields(classes: &[Class], map: &HashMap<Symbol, TypeRange>) -> Vec<AdvError> {
+    let mut errors = Vec::new();
+    for c in classes {
+        for (field, span) in c.own_fields.iter().zip(&c.field_spans) {
+            let mut parent = c.parent;
+            while let Some(p) = parent.map(|p| &classes[map[&p].0]) {
+                if let Some(i) = p.own_fields.iter().position(|f| f == field) {
+                    errors.push(AdvError::ShadowedField { span: span.to_owned(), inherited: p.field_spans[i].to_owned(), class: c.name, parent: p.name, field: *field });
+                    break;
+                }
+                parent = p.parent;
+            }
+        }
+    }
+    errors
+}
+
+// `Object` and `Null`, which every program needs
+pub fn builtin_classes() -> Vec<Class> {
+    vec![
+        Class {
+            name: Symbol::intern("Object"),
+            module: None,
+            parent: None,
+            own_fields: vec![],
+            field_spans: vec![],
+            private_fields: vec![],
+            own_methods: vec![],
+            private: false,
+            span: Span::builtin(),
+        },
+        Class {
+            name: Symbol::intern("Null"),
+            module: None,
+            parent: None,
+            own_fields: vec![],
+            field_spans: vec![],
+            private_fields: vec![],
+            own_methods: vec![],
+            private: false,
+            span: Span::builtin(),
+        },
+    ]
+}
+
+// Private classes and members are visible inside of the module they belong to, or inside of the file for classes outside of modules
+pub fn unit_of(class: &Class) -> Symbol {
+    class.module.unwrap_or_else(|| Symbol::intern(&class.span.file))
+}
+
+// The declarations of a field or method name that's private in at least one class
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct PrivateNames {
+    pub public: bool, // Whether some other class declares it without `private`
+    pub definitions: Vec<(Symbol, Span)>, // The unit and span of every private declaration
+}
+
+fn find_private_names<'a>(declarations: impl Iterator<Item = (&'a Class, Symbol, bool, &'a Span)> + Clone) -> HashMap<Symbol, PrivateNames> {
+    let mut result: HashMap<Symbol, PrivateNames> = HashMap::new();
+    for (c, name, _, span) in declarations.clone().filter(|d| d.2) {
+        result.entry(name).or_default().definitions.push((unit_of(c), span.to_owned()));
+    }
+    for (_, name, _, _) in declarations.filter(|d| !d.2) {
+        if let Some(names) = result.get_mut(&name) {
+            names.public = true;
+        }
+    }
+    result
+}
+
+// Whether `class` itself declares the field or method, and if it's private
+fn declaration(class: &Class, member: Member, name: Symbol) -> Option<(bool, &Span)> {
+    match member {
+        Member::Class => None,
+        Member::Field => class.own_fields.iter().position(|f| *f == name).map(|i| (class.private_fields.contains(&name), &class.field_spans[i])),
+        Member::Method => class.own_methods.iter().find(|m| m.name == name).map(|m| (m.private, &m.span)),
     }
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct ClassTable {
     pub classes: Vec<Class>,
-    pub map: HashMap<String, TypeRange>, // Start is inclusive, end is exclusive
+    pub map: HashMap<Symbol, TypeRange>, // Start is inclusive, end is exclusive
+    pub selectors: Selectors,
+    pub private_fields: HashMap<Symbol, PrivateNames>,
+    pub private_methods: HashMap<Symbol, PrivateNames>,
     pub null: TypeRange,
     pub truth: TypeRange,
     pub lie: TypeRange,
@@ -26,54 +234,130 @@ pub struct ClassTable {
 
 impl ClassTable {
     pub fn create(input: &Vec<Class>) -> Result<ClassTable> {
+        let duplicates = find_duplicates(input);
+        ensure!(duplicates.is_empty(), AdvError::multiple(duplicates));
+
         let mut classes = Vec::with_capacity(input.len());
         let mut map = HashMap::with_capacity(input.len());
 
-        let mut parent_map: HashMap<Option<String>, Vec<&Class>> = HashMap::new();
+        let mut parent_map: HashMap<Option<Symbol>, Vec<&Class>> = HashMap::new();
 
         for c in input {
             if let Some(vec) = parent_map.get_mut(&c.parent) {
                 vec.push(c);
             } else {
-                parent_map.insert(c.parent.to_owned(), vec![c]);
+                parent_map.insert(c.parent, vec![c]);
             }
         }
 
-        fn add_with_parent(input: &Vec<Class>, parent: Option<String>, classes: &mut Vec<Class>, map: &mut HashMap<String, TypeRange>, parent_map: &mut HashMap<Option<String>, Vec<&Class>>) {
+        fn add_with_parent(parent: Option<Symbol>, classes: &mut Vec<Class>, map: &mut HashMap<Symbol, TypeRange>, parent_map: &mut HashMap<Option<Symbol>, Vec<&Class>>) {
             if let Some(pclasses) = parent_map.remove(&parent) {
                 for c in pclasses {
                     let start = classes.len();
                     classes.push(c.to_owned());
-                    add_with_parent(input, Some(c.name.to_owned()), classes, map, parent_map);
+                    add_with_parent(Some(c.name), classes, map, parent_map);
                     let end = classes.len();
                     
-                    map.insert(c.name.to_owned(), TypeRange(start, end));
+                    map.insert(c.name, TypeRange(start, end));
                 }
             }
         }
 
-        add_with_parent(input, None, &mut classes, &mut map, &mut parent_map);
+        add_with_parent(None, &mut classes, &mut map, &mut parent_map);
+
+        ensure!(parent_map.is_empty(), AdvError::multiple(parent_map.values().flatten().map(|c| AdvError::InvalidParent { span: c.span.clone(), class: c.name, parent: c.parent.unwrap() }).collect()));
 
-        ensure!(parent_map.len() == 0, "Classes {:?} have invalid parents ({:?})", parent_map.values().flatten().map(|c| c.name.to_owned()).collect::<Vec<_>>(), parent_map.keys().map(Option::to_owned).map(Option::unwrap).collect::<Vec<_>>());
+        let shadowed = find_shadowed_fields(&classes, &map);
+        ensure!(shadowed.is_empty(), AdvError::multiple(shadowed));
+
+        let selectors = collect_selectors(&classes);
+        let table = Self::from_parts(classes, map, selectors)?;
+
+        let hidden_parents = table.classes.iter().enumerate().filter_map(|(i, c)| table.check_access(i, Member::Class, c.parent?, false, &c.span).err()).collect::<Vec<_>>();
+        ensure!(hidden_parents.is_empty(), AdvError::multiple(hidden_parents));
+        Ok(table)
+    }
 
-        let null = map.get("Null").unwrap().to_owned(); // This one will always unwrap
-        let truth = map.get("True").unwrap_or(&TypeRange::EMPTY).to_owned();
-        let lie = map.get("False").unwrap_or(&TypeRange::EMPTY).to_owned();
+    // `classes` has to be already ordered so that every entry of `map` is a valid range
+    pub fn from_parts(classes: Vec<Class>, map: HashMap<Symbol, TypeRange>, selectors: Selectors) -> Result<ClassTable> {
+        let null = map.get(&"Null".into()).ok_or(AdvError::UnknownClass { name: "Null".to_string() })?.to_owned();
+        let truth = map.get(&"True".into()).unwrap_or(&TypeRange::EMPTY).to_owned();
+        let lie = map.get(&"False".into()).unwrap_or(&TypeRange::EMPTY).to_owned();
+        let private_fields = find_private_names(classes.iter().flat_map(|c| c.own_fields.iter().zip(&c.field_spans).map(move |(f, span)| (c, *f, c.private_fields.contains(f), span))));
+        let private_methods = find_private_names(classes.iter().flat_map(|c| c.own_methods.iter().map(move |m| (c, m.name, m.private, &m.span))));
 
         Ok(ClassTable {
             classes,
             map,
+            selectors,
+            private_fields,
+            private_methods,
             null,
             truth,
             lie,
         })
     }
 
-    pub fn get_class_id(&self, name: &str) -> Result<usize> {
-        Ok(self.map.get(name).with_context(|| format!("Couldn't find a class named {}", name))?.0)
+    pub fn get_class_id(&self, name: Symbol) -> Result<usize> {
+        Ok(self.map.get(&name).ok_or_else(|| AdvError::UnknownClass { name: name.to_string() })?.0)
     }
 
-    pub fn get_class(&self, name: &str) -> Result<&Class> {
+    // Like `get_class_id`, without interning `name`
+    pub fn lookup_class_id(&self, name: &str) -> Result<usize> {
+        let range = Symbol::lookup(name).and_then(|name| self.map.get(&name));
+        Ok(range.ok_or_else(|| AdvError::UnknownClass { name: name.to_owned() })?.0)
+    }
+
+    pub fn get_class(&self, name: Symbol) -> Result<&Class> {
         self.get_class_id(name).map(|i| &self.classes[i])
     }
+
+    // Checks that code inside of class `from` can refer to `name`, `on_this` means that the member is accessed through `this`
+    // Other objects are only known at runtime, so accessing their members fails only if every declaration of the name is hidden
+    // The closest ancestor of `class` (including itself) that declares the member, whether it's private there and where
+    fn declaring(&self, class: usize, member: Member, name: Symbol) -> Option<(&Class, bool, &Span)> {
+        let mut class = Some(&self.classes[class]);
+        while let Some(c) = class {
+            if let Some((private, definition)) = declaration(c, member, name) {
+                return Some((c, private, definition));
+            }
+            class = c.parent.map(|p| &self.classes[self.map[&p].0]);
+        }
+        None
+    }
+
+    // The unit that the member used on an instance of `class` is private to, if it's private at all
+    pub fn private_unit(&self, class: usize, member: Member, name: Symbol) -> Option<Symbol> {
+        self.declaring(class, member, name).filter(|(_, private, _)| *private).map(|(c, _, _)| unit_of(c))
+    }
+
+    pub fn check_access(&self, from: usize, member: Member, name: Symbol, on_this: bool, span: &Span) -> Result<()> {
+        let hidden = |unit: Symbol, definition: &Span| AdvError::PrivateAccess { span: span.to_owned(), definition: definition.to_owned(), member, name, unit };
+        let names = match member {
+            Member::Class => {
+                if let Some(c) = self.map.get(&name).map(|r| &self.classes[r.0]).filter(|c| c.private) {
+                    ensure!(unit_of(c) == unit_of(&self.classes[from]), hidden(unit_of(c), &c.span));
+                }
+                return Ok(());
+            },
+            Member::Field => self.private_fields.get(&name),
+            Member::Method => self.private_methods.get(&name),
+        };
+        let Some(names) = names else {
+            return Ok(());
+        };
+        let unit = unit_of(&self.classes[from]);
+
+        if on_this {
+            if let Some((c, private, definition)) = self.declaring(from, member, name) {
+                ensure!(!private || unit_of(c) == unit, hidden(unit_of(c), definition));
+                return Ok(());
+            }
+        }
+        if names.public || names.definitions.iter().any(|(u, _)| *u == unit) {
+            return Ok(());
+        }
+        let (unit, definition) = &names.definitions[0];
+        bail!(hidden(*unit, definition))
+    }
 }
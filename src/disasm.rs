@@ -0,0 +1,138 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::symbol::*;
+use crate::error::*;
+use crate::class_table::*;
+use crate::opcode::*;
+use crate::opcode::OpCode::*;
+
+fn range_name(class_table: &ClassTable, range: &TypeRange) -> String {
+    if *range == TypeRange::EMPTY {
+        "nothing".to_string()
+    } else {
+        class_table.classes[range.0].name.to_string()
+    }
+}
+
+// The class that originally defined the method, found by walking up the parents for as long as they share it
+fn method_owner(class_table: &ClassTable, classes: &[CompiledClass], class: usize, method: &Rc<CompiledMethod>) -> Symbol {
+    let mut owner = class;
+    while let Some(parent) = class_table.classes[owner].parent.and_then(|p| class_table.get_class_id(p).ok()) {
+        if !classes[parent].methods.iter().any(|m| Rc::ptr_eq(m, method)) {
+            break;
+        }
+        owner = parent;
+    }
+    class_table.classes[owner].name
+}
+
+fn field_owner(class_table: &ClassTable, class: usize, field: Symbol) -> Symbol {
+    let mut owner = class;
+    while let Some(parent) = class_table.classes[owner].parent.and_then(|p| class_table.get_class_id(p).ok()) {
+        if !class_table.classes[owner].own_fields.contains(&field) {
+            owner = parent;
+        } else {
+            break;
+        }
+    }
+    class_table.classes[owner].name
+}
+
+fn disassemble_method(out: &mut String, class_table: &ClassTable, fields: &[Symbol], method: &CompiledMethod) {
+    let Some(body) = &method.body else {
+        writeln!(out, "    method {}/{} (builtin)", method.name, method.params_count).unwrap();
+        return;
+    };
+    writeln!(out, "    method {}/{} (locals: {})", method.name, method.params_count, method.locals_size).unwrap();
+
+    let mut targets = body.iter().filter_map(|op| if let Jump(_, location) = op { Some(*location) } else { None }).collect::<Vec<_>>();
+    targets.sort();
+    targets.dedup();
+    let label = |location: usize| format!("L{}", targets.iter().position(|t| *t == location).unwrap());
+    let field = |index: usize| fields.get(index).map(|s| s.as_str()).unwrap_or("?");
+
+    for (i, op) in body.iter().enumerate() {
+        if targets.contains(&i) {
+            writeln!(out, "      {}:", label(i)).unwrap();
+        }
+
+        let text = match op {
+            New(class) => format!("New {class} ({})", class_table.classes.get(*class).map(|c| c.name.as_str()).unwrap_or("?")),
+            GetV(id) => format!("GetV {id}"),
+            This => "This".to_string(),
+            GetF(name, _) => format!("GetF {name}"),
+            GetFI(index) => format!("GetFI {index} ({})", field(*index)),
+            Call(selector, argc, _) => format!("Call {}/{argc}", class_table.selectors.names().get(*selector).map(|s| s.as_str()).unwrap_or("?")),
+            Is(range) => format!("Is {}..{} ({})", range.0, range.1, range_name(class_table, range)),
+            Equals => "Equals".to_string(),
+            SetV(id) => format!("SetV {id}"),
+            SetF(name, _) => format!("SetF {name}"),
+            SetFI(index) => format!("SetFI {index} ({})", field(*index)),
+            Return => "Return".to_string(),
+            Jump(true, location) => format!("JumpIfTrue {}", label(*location)),
+            Jump(false, location) => format!("JumpIfFalse {}", label(*location)),
+            Recurse => "Recurse (tail call)".to_string(),
+            Pop => "Pop".to_string(),
+        };
+        if let Some(span) = method.spans.get(i) {
+            writeln!(out, "        {i:>4}  {text:<40} ; {span}").unwrap();
+        } else {
+            writeln!(out, "        {i:>4}  {text}").unwrap();
+        }
+    }
+
+    if targets.contains(&body.len()) {
+        writeln!(out, "      {}:", label(body.len())).unwrap();
+    }
+}
+
+pub fn disassemble(class_table: &ClassTable, classes: &[CompiledClass], class_name: Option<&str>, method_name: Option<&str>) -> Result<String> {
+    let ids = if let Some(name) = class_name {
+        let id = class_table.get_class_id(name.into())?;
+        if let Some(method) = method_name {
+            ensure!(classes[id].methods.iter().any(|m| m.name == method), AdvError::UnknownMethod { class: Some(class_table.classes[id].name), method: method.to_owned() });
+        }
+        vec![id]
+    } else {
+        (0..classes.len()).collect()
+    };
+
+    let mut out = String::new();
+    for id in ids {
+        let class = &class_table.classes[id];
+        let compiled = &classes[id];
+        let own = |m: &&Rc<CompiledMethod>| method_owner(class_table, classes, id, m) == class.name;
+        let methods = compiled.methods.iter().filter(|m| method_name.is_none_or(|name| m.name == name)).collect::<Vec<_>>();
+        if method_name.is_some() && !methods.iter().any(own) && class_name.is_none() {
+            continue;
+        }
+
+        let range = class_table.map[&class.name];
+        let parent = class.parent.as_ref().map(|p| format!(" extends {p}")).unwrap_or_default();
+        writeln!(out, "class {} (id {id}){parent}, type range {}..{}", class.name, range.0, range.1).unwrap();
+        for (i, f) in compiled.fields.iter().enumerate() {
+            let owner = field_owner(class_table, id, *f);
+            if owner == class.name {
+                writeln!(out, "    field {i}: {f}").unwrap();
+            } else {
+                writeln!(out, "    field {i}: {f} (inherited from {owner})").unwrap();
+            }
+        }
+
+        for m in &methods {
+            if own(m) {
+                disassemble_method(&mut out, class_table, &compiled.fields, m);
+            } else {
+                writeln!(out, "    method {}/{} (inherited from {})", m.name, m.params_count, method_owner(class_table, classes, id, m)).unwrap();
+            }
+        }
+        out.push('\n');
+    }
+
+    if let Some(name) = method_name {
+        ensure!(!out.is_empty(), AdvError::UnknownMethod { class: None, method: name.to_owned() });
+    }
+
+    Ok(out)
+}
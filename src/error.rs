@@ -0,0 +1,224 @@
+use std::{fmt, io};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::symbol::*;
+use crate::syntax::*;
+use crate::interpreter::RuntimeError;
+
+pub type Result<T, E = AdvError> = std::result::Result<T, E>;
+
+// Like anyhow's macros, but they take an error value instead of a format string
+macro_rules! bail {
+    ($err:expr) => {
+        return Err($err.into())
+    };
+}
+
+macro_rules! ensure {
+    ($cond:expr, $err:expr) => {
+        match $cond {
+            true => (),
+            false => return Err($err.into()),
+        }
+    };
+}
+
+pub(crate) use {bail, ensure};
+
+#[derive(Debug)]
+pub enum AdvError {
+    Io { path: PathBuf, error: io::Error },
+    ImportCycle { chain: Vec<PathBuf> }, // Starts and ends with the same file
+    ImportNotFound { import: String, importer: PathBuf, tried: Vec<PathBuf> },
+    Lex { span: Span, unexpected: Option<char> }, // None means that the file ended too early
+    Parse { span: Span, message: String },
+    VersionMismatch { target: String },
+    InvalidMetadata(String),
+    InvalidParent { span: Span, class: Symbol, parent: Symbol },
+    DuplicateClass { span: Span, previous: Span, class: Symbol },
+    DuplicateField { span: Span, previous: Span, class: Symbol, field: Symbol },
+    DuplicateMethod { span: Span, previous: Span, class: Symbol, method: Symbol },
+    ShadowedField { span: Span, inherited: Span, class: Symbol, parent: Symbol, field: Symbol },
+    // `unit` is the module or file that the private declaration at `definition` belongs to
+    PrivateAccess { span: Span, definition: Span, member: Member, name: Symbol, unit: Symbol },
+    // Names that might've come from a host, which aren't interned unless some class uses them
+    UnknownClass { name: String },
+    UnknownMethod { class: Option<Symbol>, method: String },
+    NoEntrypoint,
+    BuiltinArity { class: Symbol, method: Symbol, declared: usize, expected: usize },
+    // The class and method are filled in once the error reaches the code compiling the whole class
+    Compile { span: Span, class: Option<Symbol>, method: Option<Symbol>, message: String },
+    Verify { span: Option<Span>, class: Option<Symbol>, method: Symbol, message: String },
+    Bytecode(String),
+    InvalidConfig(String),
+    Runtime(RuntimeError),
+    Multiple(Vec<AdvError>),
+}
+
+impl AdvError {
+    pub fn multiple(mut errors: Vec<AdvError>) -> Self {
+        if errors.len() == 1 {
+            errors.pop().unwrap()
+        } else {
+            Self::Multiple(errors)
+        }
+    }
+
+    pub fn in_method(self, class: Symbol, method: Symbol) -> Self {
+        match self {
+            Self::Compile { span, class: None, method: None, message } => Self::Compile { span, class: Some(class), method: Some(method), message },
+            Self::Verify { span, class: None, method, message } => Self::Verify { span, class: Some(class), method, message },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for AdvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "Failed to read '{}': {error}", path.display()),
+            Self::ImportCycle { chain } => write!(f, "Import cycle: {}", chain.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(" -> ")),
+            Self::ImportNotFound { import, importer, tried } => {
+                write!(f, "Couldn't find '{import}', imported by '{}'. Tried:", importer.display())?;
+                for path in tried {
+                    write!(f, "\n    {}", path.display())?;
+                }
+                Ok(())
+            },
+            Self::Lex { span, unexpected: None } => write!(f, "{span}: Unexpected end of file"),
+            Self::Lex { span, unexpected: Some(c) } => write!(f, "{span}: Unexpected '{c}' character"),
+            Self::Parse { span, message } => write!(f, "{span}: {message}"),
+            Self::VersionMismatch { target } => write!(f, "Incompatible version! (program targets '{target}', running '{CURRENT_VERSION}')"),
+            Self::InvalidMetadata(message) => write!(f, "{message}"),
+            Self::InvalidParent { span, class, parent } => write!(f, "{span}: Class '{class}' has an invalid parent '{parent}'"),
+            Self::DuplicateClass { span, previous, class } => write!(f, "{span}: Class '{class}' is already defined at {previous}"),
+            Self::DuplicateField { span, previous, class, field } => write!(f, "{span}: Field '{field}' of class '{class}' is already defined at {previous}"),
+            Self::DuplicateMethod { span, previous, class, method } => write!(f, "{span}: Method '{method}' of class '{class}' is already defined at {previous}"),
+            Self::ShadowedField { span, inherited, class, parent, field } => write!(f, "{span}: Field '{field}' of class '{class}' shadows the one inherited from '{parent}', defined at {inherited}"),
+            Self::PrivateAccess { span, definition, member, name, unit } => write!(f, "{span}: {member} '{name}' is private to '{unit}', defined at {definition}"),
+            Self::UnknownClass { name } => write!(f, "Couldn't find a class named {name}"),
+            Self::UnknownMethod { class: Some(class), method } => write!(f, "Class '{class}' doesn't define method '{method}'"),
+            Self::UnknownMethod { class: None, method } => write!(f, "Couldn't find a method named {method}"),
+            Self::NoEntrypoint => write!(f, "No entrypoint defined"),
+            Self::BuiltinArity { class, method, declared, expected } => write!(f, "Method '{method}' of class '{class}' is declared with {declared} parameters, but its builtin takes {expected}"),
+            Self::Compile { span, class, method, message } => {
+                write!(f, "{span}: {message}")?;
+                if let (Some(class), Some(method)) = (class, method) {
+                    write!(f, " (in method '{class}.{method}')")?;
+                }
+                Ok(())
+            },
+            Self::Verify { span, class, method, message } => {
+                match class {
+                    Some(class) => write!(f, "Invalid method '{class}.{method}': ")?,
+                    None => write!(f, "Invalid method '{method}': ")?,
+                }
+                if let Some(span) = span {
+                    write!(f, "{span}: ")?;
+                }
+                write!(f, "{message}")
+            },
+            Self::Bytecode(message) => write!(f, "{message}"),
+            Self::InvalidConfig(message) => write!(f, "{message}"),
+            Self::Runtime(e) => write!(f, "{e}"),
+            Self::Multiple(errors) => write!(f, "{}", errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")),
+        }
+    }
+}
+
+impl std::error::Error for AdvError {}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Member {
+    Class,
+    Field,
+    Method,
+}
+
+impl fmt::Display for Member {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Class => write!(f, "Class"),
+            Self::Field => write!(f, "Field"),
+            Self::Method => write!(f, "Method"),
+        }
+    }
+}
+
+impl From<RuntimeError> for AdvError {
+    fn from(e: RuntimeError) -> Self {
+        Self::Runtime(e)
+    }
+}
+
+// Errors outside of a running method, like failing to allocate an entrypoint, don't have a backtrace
+impl From<RuntimeErrorKind> for AdvError {
+    fn from(kind: RuntimeErrorKind) -> Self {
+        Self::Runtime(RuntimeError {
+            kind,
+            span: None,
+            backtrace: Default::default(),
+        })
+    }
+}
+
+// Problems that don't stop the compilation, they're left to the caller to report
+#[derive(PartialEq, Clone, Debug)]
+pub struct Warning {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: Warning: {}", self.span, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum RuntimeErrorKind {
+    StackOverflow,
+    StackUnderflow,
+    StackOutOfBounds { range: Range<usize>, len: usize },
+    OutOfMemory { max_heap_size: usize },
+    UninitializedVariable,
+    DanglingObject,
+    FieldOutOfBounds { index: usize, len: usize },
+    UndefinedField { class: Symbol, field: String },
+    UndefinedMethod { class: Symbol, method: Symbol },
+    ArgumentCount { class: Symbol, method: Symbol, expected: usize, provided: usize },
+    NotEnoughArguments { method: Symbol },
+    UnbalancedStack { method: Symbol },
+    MissingBody { method: Symbol },
+    NotACharacter { class: Symbol },
+    // Accesses that the compiler couldn't resolve to a single declaration are checked once they happen
+    PrivateAccess { member: Member, name: Symbol, unit: Symbol },
+    Io(io::Error),
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::StackOverflow => write!(f, "adv stack overflow"),
+            Self::StackUnderflow => write!(f, "adv stack underflow"),
+            Self::StackOutOfBounds { range, len } if range.len() == 1 => write!(f, "Stack slot {} is out of bounds (stack size is {len})", range.start),
+            Self::StackOutOfBounds { range, len } => write!(f, "Stack slots {range:?} are out of bounds (stack size is {len})"),
+            Self::OutOfMemory { max_heap_size } => write!(f, "Out of memory (the heap is limited to {max_heap_size} objects)"),
+            Self::UninitializedVariable => write!(f, "Attempted to use a variable before its initialization"),
+            Self::DanglingObject => write!(f, "Attempted to use an object that was already collected"),
+            Self::FieldOutOfBounds { index, len } => write!(f, "Field {index} is out of bounds (the object has {len} fields)"),
+            Self::UndefinedField { class, field } => write!(f, "Type '{class}' doesn't define field '{field}'"),
+            Self::UndefinedMethod { class, method } => write!(f, "Type '{class}' doesn't define method '{method}'"),
+            Self::ArgumentCount { class, method, expected, provided } => write!(f, "Method '{class}.{method}' takes {expected} arguments, but {provided} were provided"),
+            Self::NotEnoughArguments { method } => write!(f, "Not enough arguments were provided to '{method}'"),
+            Self::UnbalancedStack { method } => write!(f, "Unbalanced stack in '{method}'"),
+            Self::MissingBody { method } => write!(f, "Attempted to run method '{method}', which doesn't have a body"),
+            Self::NotACharacter { class } => write!(f, "Expected a character class, got an instance of '{class}'"),
+            Self::PrivateAccess { member, name, unit } => write!(f, "{member} '{name}' is private to '{unit}'"),
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeErrorKind {}
@@ -1,99 +1,416 @@
-use std::alloc::{alloc, dealloc, Layout};
-use std::ptr;
-use std::collections::HashSet;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::num::Wrapping;
+use std::time::{Duration, Instant};
+use std::env;
 
+use crate::error::*;
+
+// A reference to an object on the heap of a GC, its fields can only be reached through that GC
+// Field-less objects don't take up any space on the heap, they only get a unique `index` so that they can be told apart
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Object {
-    pub class: usize,
-    pub contents: *mut [Object],
+    class: usize, // Only the GC can change it, see `remap_classes`
+    len: u32, // Amount of fields, which is also the arena of the object
+    generation: u32, // Of the slot when the object was allocated, it changes once the slot is freed
+    index: usize,
 }
 
 impl Object {
-    pub fn get(&self, index: usize) -> Object {
-        unsafe {
-            (*self.contents)[index]
+    // Marks uninitialized slots, it's never equal to a real object since field-less objects get indices starting from 1
+    // `=` compares identity, so normally every instance of a field-less class (`True`, `'a'`, `3`...) is distinct from all the others
+    // Classes made singletons through the `singleton` metadata entry are the exception: all of their instances are equal
+    pub const TRUE_NULL: Self = Self { class: 0, len: 0, generation: 0, index: 0 };
+
+    // The only instance of a singleton class, it doesn't need to be allocated since the class is a part of its identity
+    pub fn singleton(class: usize) -> Self {
+        Self {
+            class,
+            len: 0,
+            generation: 0,
+            index: usize::MAX,
         }
     }
-    
-    pub fn set(&self, index: usize, value: Object) {
-        unsafe {
-            (*self.contents)[index] = value
-        }
+
+    pub fn class(&self) -> usize {
+        self.class
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+// Allocations are grouped by size, each size gets its own arena
+// Freeing a slot bumps its generation, so objects that still refer to it can't reach whoever reuses it
+const CHUNK_SLOTS: usize = 256; // Arenas grow by this many slots at a time
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum State {
+    Free,
+    Allocated,
+    Marked,
 }
 
-unsafe fn ptr_len(ptr: *const [Object]) -> usize {
-    (&*ptr).len()
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    state: State,
+    generation: u32,
 }
 
+#[derive(Debug)]
+struct Arena {
+    size: usize,
+    slots: Vec<Slot>,
+    fields: Vec<Object>, // `size` of them for every slot
+    free: Vec<usize>,
+}
+
+impl Arena {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            slots: Vec::new(),
+            fields: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn take(&mut self) -> (usize, u32) {
+        if self.free.is_empty() {
+            let start = self.slots.len();
+            self.slots.resize(start + CHUNK_SLOTS, Slot { state: State::Free, generation: 0 });
+            self.fields.resize(self.slots.len() * self.size, Object::TRUE_NULL);
+            self.free.extend((start..self.slots.len()).rev());
+        }
+        let index = self.free.pop().unwrap();
+        self.slots[index].state = State::Allocated;
+        self.fields[index * self.size..(index + 1) * self.size].fill(Object::TRUE_NULL);
+        (index, self.slots[index].generation)
+    }
+
+    fn is_live(&self, obj: &Object) -> bool {
+        self.slots.get(obj.index).is_some_and(|s| s.state != State::Free && s.generation == obj.generation)
+    }
+
+    // Frees every slot that wasn't marked and returns the amount of the ones that were
+    fn sweep(&mut self) -> usize {
+        let mut alive = 0;
+        self.free.clear();
+        for (i, slot) in self.slots.iter_mut().enumerate().rev() {
+            match slot.state {
+                State::Marked => {
+                    slot.state = State::Allocated;
+                    alive += 1;
+                },
+                State::Allocated => {
+                    slot.state = State::Free;
+                    slot.generation = slot.generation.wrapping_add(1);
+                    self.free.push(i);
+                },
+                State::Free => self.free.push(i),
+            }
+        }
+        alive
+    }
+}
+
+// Heap sizes are counted in objects
 #[derive(PartialEq, Clone, Debug)]
+pub struct GcConfig {
+    pub initial_heap_size: usize,
+    pub growth_factor: f64, // After a collection, the next one happens once the heap is this many times bigger than what survived
+    pub max_heap_size: Option<usize>,
+    pub verbosity: u8, // 0 is quiet, 1 logs every collection, 2 also logs timings and thresholds
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            initial_heap_size: 1024,
+            growth_factor: 2.0,
+            max_heap_size: None,
+            verbosity: 0,
+        }
+    }
+}
+
+impl GcConfig {
+    // Reads ADV_GC_HEAP_SIZE, ADV_GC_GROWTH_FACTOR, ADV_GC_MAX_HEAP_SIZE and ADV_GC_VERBOSITY, falling back to the defaults
+    pub fn from_env() -> Result<Self> {
+        fn var<T: std::str::FromStr>(name: &str) -> Result<Option<T>> {
+            match env::var(name) {
+                Ok(value) => Ok(Some(value.parse().map_err(|_| AdvError::InvalidConfig(format!("Invalid value of {name}: '{value}'")))?)),
+                Err(_) => Ok(None),
+            }
+        }
+
+        let default = Self::default();
+        let config = Self {
+            initial_heap_size: var("ADV_GC_HEAP_SIZE")?.unwrap_or(default.initial_heap_size),
+            growth_factor: var("ADV_GC_GROWTH_FACTOR")?.unwrap_or(default.growth_factor),
+            max_heap_size: var("ADV_GC_MAX_HEAP_SIZE")?.or(default.max_heap_size),
+            verbosity: var("ADV_GC_VERBOSITY")?.unwrap_or(default.verbosity),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.initial_heap_size > 0, AdvError::InvalidConfig("The initial heap size has to be at least 1".to_string()));
+        ensure!(self.growth_factor >= 1.0, AdvError::InvalidConfig("The heap growth factor can't be smaller than 1".to_string()));
+        if let Some(max) = self.max_heap_size {
+            ensure!(max >= self.initial_heap_size, AdvError::InvalidConfig("The max heap size can't be smaller than the initial one".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct GcStats {
+    pub before: usize,
+    pub after: usize,
+    pub next_collection: usize, // Heap size at which the next collection will happen
+    pub duration: Duration,
+}
+
+impl GcStats {
+    pub fn collected(&self) -> usize {
+        self.before - self.after
+    }
+}
+
+#[derive(Debug)]
 pub struct GC {
-    allocations: HashSet<*mut [Object]>,
+    arenas: Vec<Arena>, // Indexed by the size of allocations
+    allocated: usize,
+    threshold: usize, // Once this many objects are allocated, a collection is due
+    config: GcConfig,
     zero_alloc_index: Wrapping<usize>,
-    stack: *const [Object],
+    pinned: Pins,
+}
+
+// Objects kept alive by the host, with the amount of times they were pinned
+type Pins = Rc<RefCell<HashMap<Object, usize>>>;
+
+fn pin(pinned: &Pins, obj: Object) {
+    *pinned.borrow_mut().entry(obj).or_insert(0) += 1;
+}
+
+fn unpin(pinned: &Pins, obj: Object) {
+    if let Entry::Occupied(mut entry) = pinned.borrow_mut().entry(obj) {
+        *entry.get_mut() -= 1;
+        if *entry.get() == 0 {
+            entry.remove();
+        }
+    }
+}
+
+// Keeps an object alive until it's dropped, it can outlive any borrow of the GC since the pin counts are shared
+#[derive(Debug)]
+pub struct Handle {
+    obj: Object,
+    pinned: Pins,
+}
+
+impl Handle {
+    pub fn object(&self) -> Object {
+        self.obj
+    }
+
+    // Handles are only meaningful to the GC that created them
+    pub fn belongs_to(&self, gc: &GC) -> bool {
+        Rc::ptr_eq(&self.pinned, &gc.pinned)
+    }
+}
+
+impl Clone for Handle {
+    fn clone(&self) -> Self {
+        pin(&self.pinned, self.obj);
+        Self {
+            obj: self.obj,
+            pinned: self.pinned.clone(),
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unpin(&self.pinned, self.obj);
+    }
 }
 
 impl GC {
-    pub fn new(stack: *const [Object], heap_size: usize) -> Self {
+    pub fn new(config: GcConfig) -> Self {
         Self {
-            allocations: HashSet::with_capacity(heap_size),
-            zero_alloc_index: Wrapping(0),
-            stack,
+            arenas: Vec::new(),
+            allocated: 0,
+            threshold: config.initial_heap_size,
+            config,
+            zero_alloc_index: Wrapping(1), // 0 would make the first allocation equal to `TRUE_NULL`
+            pinned: Default::default(),
         }
     }
 
-    pub fn alloc(&mut self, size: usize) -> *mut [Object] {
-        if size == 0 {
-            let result = ptr::slice_from_raw_parts(self.zero_alloc_index.0 as *mut Object, 0) as *mut [Object];
+    pub fn pin(&mut self, obj: Object) {
+        pin(&self.pinned, obj);
+    }
+
+    pub fn unpin(&mut self, obj: Object) {
+        unpin(&self.pinned, obj);
+    }
+
+    pub fn handle(&mut self, obj: Object) -> Handle {
+        self.pin(obj);
+        Handle {
+            obj,
+            pinned: self.pinned.clone(),
+        }
+    }
+
+    // The fields start out as `TRUE_NULL`
+    // Allocating never collects, since only the caller knows where the roots are, see `should_collect`
+    pub fn alloc(&mut self, class: usize, len: usize) -> Result<Object, RuntimeErrorKind> {
+        if len == 0 {
+            let index = self.zero_alloc_index.0;
             self.zero_alloc_index += 1;
-            result
-        } else {
-            unsafe {
-                let layout = Layout::array::<Object>(size).expect("Invalid layout :<");
-                let allocated = ptr::slice_from_raw_parts(alloc(layout), size) as *mut [Object];
-                
-                if self.allocations.capacity() == self.allocations.len() {
-                    self.collect();
-                }
-                self.allocations.insert(allocated);
+            return Ok(Object { class, len: 0, generation: 0, index });
+        }
+        if let Some(max) = self.config.max_heap_size {
+            ensure!(self.allocated < max, RuntimeErrorKind::OutOfMemory { max_heap_size: max });
+        }
 
-                allocated
-            }
+        while self.arenas.len() <= len {
+            self.arenas.push(Arena::new(self.arenas.len()));
         }
+        let (index, generation) = self.arenas[len].take();
+        self.allocated += 1;
+        Ok(Object { class, len: len as u32, generation, index })
     }
 
-    pub fn collect(&mut self) {
-        let old = self.allocations.len();
-        unsafe {
-            let mut keep_alive = HashSet::with_capacity(self.allocations.capacity());
+    // Whether the heap grew enough for a collection, which should then happen at a point where every live object is a root
+    pub fn should_collect(&self) -> bool {
+        self.allocated >= self.threshold
+    }
 
-            fn add(keep_alive: &mut HashSet<*mut [Object]>, obj: &Object) {
-                unsafe {
-                    if ptr_len(obj.contents) != 0 {
-                        if keep_alive.insert(obj.contents) {
-                            for i in 0..ptr_len(obj.contents) {
-                                add(keep_alive, &(*obj.contents)[i]);
-                            }
-                        }
-                    }
+    pub fn allocation_count(&self) -> usize {
+        self.allocated
+    }
+
+    // Field-less objects don't live on the heap, so they're never dangling
+    pub fn is_live(&self, obj: Object) -> bool {
+        obj.len == 0 || self.arenas.get(obj.len()).is_some_and(|a| a.is_live(&obj))
+    }
+
+    pub fn fields(&self, obj: Object) -> Result<&[Object], RuntimeErrorKind> {
+        if obj.len == 0 {
+            return Ok(&[]);
+        }
+        let arena = self.arenas.get(obj.len()).filter(|a| a.is_live(&obj)).ok_or(RuntimeErrorKind::DanglingObject)?;
+        Ok(&arena.fields[obj.index * arena.size..][..arena.size])
+    }
+
+    pub fn fields_mut(&mut self, obj: Object) -> Result<&mut [Object], RuntimeErrorKind> {
+        if obj.len == 0 {
+            return Ok(&mut []);
+        }
+        let arena = self.arenas.get_mut(obj.len()).filter(|a| a.is_live(&obj)).ok_or(RuntimeErrorKind::DanglingObject)?;
+        Ok(&mut arena.fields[obj.index * arena.size..][..arena.size])
+    }
+
+    pub fn get(&self, obj: Object, index: usize) -> Result<Object, RuntimeErrorKind> {
+        self.fields(obj)?.get(index).copied().ok_or(RuntimeErrorKind::FieldOutOfBounds { index, len: obj.len() })
+    }
+
+    pub fn set(&mut self, obj: Object, index: usize, value: Object) -> Result<(), RuntimeErrorKind> {
+        *self.fields_mut(obj)?.get_mut(index).ok_or(RuntimeErrorKind::FieldOutOfBounds { index, len: obj.len() })? = value;
+        Ok(())
+    }
+
+    // Replaces the class of every object in `roots`, on the heap and among the pinned ones with `id_map[class]`
+    // Needed once class ids shift, like when the repl defines new classes, handles that already exist keep the old ids
+    pub fn remap_classes(&mut self, roots: &mut [Object], id_map: &[usize]) {
+        let remap = |obj: &mut Object| {
+            if *obj != Object::TRUE_NULL {
+                obj.class = id_map[obj.class];
+            }
+        };
+
+        roots.iter_mut().for_each(remap);
+        for arena in self.arenas.iter_mut().filter(|a| a.size != 0) {
+            for (slot, fields) in arena.slots.iter().zip(arena.fields.chunks_mut(arena.size)) {
+                if slot.state != State::Free {
+                    fields.iter_mut().for_each(remap);
                 }
             }
+        }
 
-            for i in 0..ptr_len(self.stack) { // TODO: Don't iterate over the entire thing
-                let fella = &(*self.stack)[i];
-                if fella != &Object::TRUE_NULL {
-                    add(&mut keep_alive, fella);
+        let mut pinned = self.pinned.borrow_mut();
+        *pinned = pinned.drain().map(|(mut obj, count)| {
+            remap(&mut obj);
+            (obj, count)
+        }).collect();
+    }
+
+    // `roots` are usually the live part of the stack, pinned objects are always roots
+    pub fn collect(&mut self, roots: &[Object]) -> GcStats {
+        let start = Instant::now();
+        let before = self.allocated;
+
+        // Tri-color marking: unmarked objects are white, the ones in `worklist` are gray and the rest are black
+        // Doing it with an explicit worklist means long chains of objects can't overflow the native stack
+        let mut worklist = Vec::new();
+
+        fn shade(arenas: &mut [Arena], worklist: &mut Vec<Object>, obj: Object) {
+            if let Some(arena) = arenas.get_mut(obj.len()).filter(|a| obj.len != 0 && a.is_live(&obj)) {
+                let slot = &mut arena.slots[obj.index];
+                if slot.state == State::Allocated {
+                    slot.state = State::Marked;
+                    worklist.push(obj);
                 }
             }
+        }
+
+        for root in roots.iter().chain(self.pinned.borrow().keys()) {
+            shade(&mut self.arenas, &mut worklist, *root);
+        }
 
-            for garbage in &self.allocations - &keep_alive {
-                dealloc(garbage as *mut Object as *mut u8, Layout::array::<Object>(ptr_len(garbage)).expect("Invalid layout :<"));
+        while let Some(obj) = worklist.pop() {
+            for i in 0..obj.len() {
+                let field = self.arenas[obj.len()].fields[obj.index * obj.len() + i];
+                shade(&mut self.arenas, &mut worklist, field);
             }
+        }
+
+        self.allocated = self.arenas.iter_mut().map(|a| a.sweep()).sum();
 
-            self.allocations = keep_alive;
+        let grown = (self.allocated as f64 * self.config.growth_factor) as usize;
+        self.threshold = grown.max(self.config.initial_heap_size);
+        if let Some(max) = self.config.max_heap_size {
+            self.threshold = self.threshold.min(max);
         }
-        let new = self.allocations.len();
-        eprintln!("GC | Before: {}, After: {}, Collected: {}", old, new, old - new);
+
+        let stats = GcStats {
+            before,
+            after: self.allocated,
+            next_collection: self.threshold,
+            duration: start.elapsed(),
+        };
+
+        if self.config.verbosity >= 1 {
+            eprint!("GC | Before: {}, After: {}, Collected: {}", stats.before, stats.after, stats.collected());
+            if self.config.verbosity >= 2 {
+                eprint!(", Took: {:?}, Next collection at: {}", stats.duration, stats.next_collection);
+            }
+            eprintln!();
+        }
+
+        stats
     }
 }
@@ -1,33 +1,34 @@
-use std::io::prelude::*;
-
-use anyhow::{Result, Context, bail, ensure};
+use std::fmt;
+use std::cell::Cell;
+use std::ptr;
+use std::collections::VecDeque;
+use std::ops::Range;
 
+use crate::syntax::*;
+use crate::symbol::*;
+use crate::error::*;
 use crate::class_table::*;
 use crate::opcode::*;
 use crate::opcode::OpCode::*;
 use crate::gc::*;
+use crate::builtins::*;
 
 impl Object {
-    pub const TRUE_NULL: Self = Self { class: 0, contents: std::ptr::null_mut::<[Self;0]>() as *mut [Self]}; // Technically this type could be equal to one specific instance of Null. it might cause some issues
-
-    pub fn new(ctx: &RunCtx, gc: &mut GC, class: usize) -> Self {
-        let cclass = &ctx.classes[class];
-        let len = cclass.fields.len();
-        let contents = gc.alloc(len);
-        
-        let result = Self {
-            class,
-            contents,
-        };
+    pub fn new(ctx: &RunCtx, gc: &mut GC, class: usize) -> Result<Self, RuntimeErrorKind> {
+        if ctx.singletons[class] {
+            return Ok(Self::singleton(class));
+        }
 
+        let len = ctx.classes[class].fields.len();
+        let result = gc.alloc(class, len)?;
         for i in 0..len {
-            result.set(i, Self::null(ctx, gc));
+            let null = Self::null(ctx, gc)?;
+            gc.set(result, i, null)?;
         }
-
-        result
+        Ok(result)
     }
 
-    pub fn new_r(ctx: &RunCtx, gc: &mut GC, range: TypeRange) -> Self {
+    pub fn new_r(ctx: &RunCtx, gc: &mut GC, range: TypeRange) -> Result<Self, RuntimeErrorKind> {
         if range == TypeRange::EMPTY {
             Self::null(ctx, gc)
         } else {
@@ -35,11 +36,11 @@ impl Object {
         }
     }
 
-    pub fn null(ctx: &RunCtx, gc: &mut GC) -> Self {
+    pub fn null(ctx: &RunCtx, gc: &mut GC) -> Result<Self, RuntimeErrorKind> {
         Self::new_r(ctx, gc, ctx.class_table.null)
     }
 
-    pub fn bool(ctx: &RunCtx, gc: &mut GC, b: bool) -> Self {
+    pub fn bool(ctx: &RunCtx, gc: &mut GC, b: bool) -> Result<Self, RuntimeErrorKind> {
         if b {
             Self::new_r(ctx, gc, ctx.class_table.truth)
         } else {
@@ -47,180 +48,469 @@ impl Object {
         }
     }
     
-    pub fn class_name<'a>(&self, class_table: &'a ClassTable) -> &'a str {
-        &class_table.classes[self.class].name
+    pub fn class_name(&self, class_table: &ClassTable) -> &'static str {
+        class_table.classes[self.class()].name.as_str()
     }
     
     pub fn is(&self, range: &TypeRange) -> bool {
-        range.matches(self.class)
+        range.matches(self.class())
     }
 }
 
+#[derive(Default, Debug)]
+pub struct CacheCounter {
+    pub hits: Cell<u64>,
+    pub misses: Cell<u64>,
+}
+
+impl fmt::Display for CacheCounter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (hits, misses) = (self.hits.get(), self.misses.get());
+        let rate = if hits + misses == 0 { 100.0 } else { hits as f64 * 100.0 / (hits + misses) as f64 };
+        write!(f, "{hits} hits, {misses} misses ({rate:.2}% hit rate)")
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct CacheStats {
+    pub call: CacheCounter,
+    pub get_field: CacheCounter,
+    pub set_field: CacheCounter,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Inline caches | Call: {}", self.call)?;
+        writeln!(f, "Inline caches | GetF: {}", self.get_field)?;
+        write!(f, "Inline caches | SetF: {}", self.set_field)
+    }
+}
+
+// Returns the slot cached for the class, or resolves it and remembers it for next time
+fn cached_slot(cache: &InlineCache, counter: &CacheCounter, class: usize, resolve: impl FnOnce() -> Result<usize, RuntimeErrorKind>) -> Result<usize, RuntimeErrorKind> {
+    if let Some(slot) = cache.lookup(class) {
+        counter.hits.set(counter.hits.get() + 1);
+        Ok(slot)
+    } else {
+        counter.misses.set(counter.misses.get() + 1);
+        let slot = resolve()?;
+        cache.insert(class, slot);
+        Ok(slot)
+    }
+}
+
+// Private members can only be used by methods from the same unit, the compiler can't tell which declaration
+// a name refers to unless it's used on `this`, so the ones that the caches are filled with get checked here
+fn field_slot(ctx: &RunCtx, method: &CompiledMethod, class: usize, name: Symbol) -> Result<usize, RuntimeErrorKind> {
+    let index = ctx.classes[class].fields.iter().position(|f| *f == name)
+        .ok_or_else(|| RuntimeErrorKind::UndefinedField { class: ctx.class_table.classes[class].name, field: name.to_string() })?;
+    if let Some(unit) = ctx.class_table.private_unit(class, Member::Field, name) {
+        ensure!(unit == method.unit, RuntimeErrorKind::PrivateAccess { member: Member::Field, name, unit });
+    }
+    Ok(index)
+}
+
 pub struct RunCtx {
     pub class_table: ClassTable,
     pub classes: Vec<CompiledClass>,
+    pub singletons: Vec<bool>, // Indexed by class id
     pub entrypoint: Object,
+    pub builtins: Builtins,
+    pub cache_stats: CacheStats,
 }
 
 impl RunCtx {
-    pub fn new(gc: &mut GC, class_table: ClassTable, classes: Vec<CompiledClass>, entrypoint_class: usize) -> Self {
+    pub fn new(gc: &mut GC, class_table: ClassTable, classes: Vec<CompiledClass>, singletons: &[String], entrypoint_class: usize, builtins: Builtins) -> Result<Self> {
+        builtins.check(&class_table, &classes)?;
         let mut result = Self {
+            singletons: find_singletons(&class_table, &classes, singletons)?,
             class_table,
             classes,
             entrypoint: Object::TRUE_NULL,
+            builtins,
+            cache_stats: CacheStats::default(),
         };
-        result.entrypoint = Object::new(&result, gc, entrypoint_class);
-        result
+        result.entrypoint = Object::new(&result, gc, entrypoint_class)?;
+        gc.pin(result.entrypoint);
+        Ok(result)
+    }
+}
+
+// `names` holds the classes listed in `singleton` metadata entries, '*' stands for every class without fields
+pub fn find_singletons(class_table: &ClassTable, classes: &[CompiledClass], names: &[String]) -> Result<Vec<bool>> {
+    let mut result = vec![false; classes.len()];
+    for name in names {
+        if name == "*" {
+            for (i, c) in classes.iter().enumerate() {
+                result[i] |= c.fields.is_empty();
+            }
+        } else {
+            // Character classes can't be named directly from a string, so `'a'` can be written as just `a`
+            let id = class_table.get_class_id(name.as_str().into()).or_else(|_| class_table.get_class_id(format!("'{name}'").as_str().into())).map_err(|_| AdvError::InvalidMetadata(format!("Couldn't find singleton class '{name}'")))?;
+            ensure!(classes[id].fields.is_empty(), AdvError::InvalidMetadata(format!("Class '{name}' can't be a singleton, because it has fields")));
+            result[id] = true;
+        }
+    }
+    Ok(result)
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Frame {
+    pub class: Symbol,
+    pub method: Symbol,
+    pub opcode: usize,
+    pub span: Option<Span>,
+    pub tail_calls: usize,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {}.{} (", self.class, self.method)?;
+        if let Some(span) = &self.span {
+            write!(f, "{span}, ")?;
+        }
+        write!(f, "opcode {})", self.opcode)?;
+        if self.tail_calls != 0 {
+            write!(f, " after {} tail calls", self.tail_calls)?;
+        }
+        Ok(())
     }
 }
 
-pub fn run(ctx: &RunCtx, gc: &mut GC, char_stack: &mut String, full_stack: &mut [Object], method: &CompiledMethod) -> Result<Object> {
-    let (this, rest) = full_stack.split_first_mut().unwrap();
-    if let Some(ops) = &method.body {
-        let (vars, stack) = rest.split_at_mut(method.locals_size);
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct AdvBacktrace {
+    pub frames: Vec<(Frame, usize)>, // Innermost frame first, with how many times in a row it was on the stack
+    pub omitted: usize, // Frames dropped from the middle once there are more than `MAX_RUNS` runs
+}
 
-        let mut stack_pos = 0;
+impl AdvBacktrace {
+    pub const MAX_RUNS: usize = 64;
+}
 
-        macro_rules! push {
-            ($value:expr) => {{
-                stack[stack_pos] = $value;
-                stack_pos += 1;
-            }}
+impl fmt::Display for AdvBacktrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = Vec::new();
+        for (i, (frame, count)) in self.frames.iter().enumerate() {
+            if i == Self::MAX_RUNS / 2 && self.omitted != 0 {
+                lines.push(format!("    ... {} more frames", self.omitted));
+            }
+            lines.push(format!("    {frame}"));
+            if *count > 1 {
+                lines.push(format!("    ... repeated {} times", count - 1));
+            }
         }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
 
-        macro_rules! pop {
-            () => {{
-                stack_pos -= 1;
-                let res = stack[stack_pos];
-                stack[stack_pos] = Object::TRUE_NULL; // cuz garbage collector
-                res
-            }}
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub span: Option<Span>, // Where the innermost frame was when the error happened
+    pub backtrace: AdvBacktrace,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(span) = &self.span {
+            write!(f, "{span}: ")?;
+        }
+        write!(f, "{}", self.kind)?;
+        if !self.backtrace.frames.is_empty() {
+            write!(f, "\n{}", self.backtrace)?;
         }
+        Ok(())
+    }
+}
 
-        let mut i = 0;
-        while i < ops.len() {
-            match &ops[i] {
-                New(class) => push!(Object::new(ctx, gc, *class)),
-                GetV(id) => {
-                    ensure!(vars[*id] != Object::TRUE_NULL, "Attempted to use a variable before its initialization");
-                    push!(vars[*id]);
-                },
-                This => push!(*this),
-                GetF(name) => {
-                    let obj = pop!();
-                    if let Some(index) = ctx.classes[obj.class].fields.iter().position(|f| f == name) {
-                        push!(obj.get(index));
-                    } else {
-                        bail!("Type '{}' doesn't define field '{}'", obj.class_name(&ctx.class_table), name);
-                    }
-                },
-                GetFI(index) => {
-                    let obj = pop!();
-                    push!(obj.get(*index));
-                },
-                Call(name, argc) => {
-                    let obj_i = stack_pos - argc - 1;
-                    let obj = stack[obj_i];
-                    let method = ctx.classes[obj.class].methods.iter().find(|m| m.name == *name).with_context(|| format!("Type '{}' doesn't define method '{}'", obj.class_name(&ctx.class_table), name))?;
-                    ensure!(*argc == method.params_count, "Method '{}.{}' takes {} arguments, but {} were provided", obj.class_name(&ctx.class_table), name, method.params_count, argc);
-
-                    stack_pos = obj_i;
-                    push!(run(ctx, gc, char_stack, &mut stack[obj_i..], method).with_context(|| format!("Failed to run method '{}.{}'", obj.class_name(&ctx.class_table), name))?);
-                },
-                Is(range) => {
-                    push!(Object::bool(ctx, gc, pop!().is(&range)));
-                },
-                Equals => {
-                    let a = pop!();
-                    let b = pop!();
-                    push!(Object::bool(ctx, gc, a == b));
-                }
-                SetV(id) => vars[*id] = pop!(),
-                SetF(name) => {
-                    let value = pop!();
-                    let obj = pop!();
-
-                    if let Some(index) = ctx.classes[obj.class].fields.iter().position(|f| f == name) {
-                        obj.set(index, value)
-                    } else {
-                        bail!("Type '{}' doesn't define field '{}'", obj.class_name(&ctx.class_table), name);
-                    }
-                },
-                SetFI(index) => {
-                    let value = pop!();
-                    let obj = pop!();
-
-                    obj.set(*index, value);
-                },
-                Return => {
-                    assert_eq!(stack_pos, 1);
-                    return Ok(pop!());
-                },
-                Jump(expected, location) => {
-                    if !expected ^ (pop!().is(&ctx.class_table.truth)) {
-                        i = *location;
-                        continue;
-                    }
-                },
-                Recurse => {
-                    vars[..method.params_count].clone_from_slice(&stack[1..stack_pos]);
-                    vars[method.params_count..].fill(Object::TRUE_NULL);
-                    stack[..stack_pos].fill(Object::TRUE_NULL);
-                    stack_pos = 0;
-                    i = 0;
+impl std::error::Error for RuntimeError {}
+
+pub struct VmStack {
+    slots: Vec<Object>,
+    max_size: usize,
+}
+
+impl VmStack {
+    pub const DEFAULT_MAX_SIZE: usize = 1 << 24;
+    const INITIAL_CAPACITY: usize = 1024;
+
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(Self::INITIAL_CAPACITY.min(max_size)),
+            max_size,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    // Every slot below `len`, these are the only ones the GC has to look at
+    pub fn live(&self) -> &[Object] {
+        &self.slots
+    }
+
+    fn reserve(&mut self, additional: usize) -> Result<(), RuntimeErrorKind> {
+        ensure!(self.slots.len() + additional <= self.max_size, RuntimeErrorKind::StackOverflow);
+        self.slots.try_reserve(additional).map_err(|_| RuntimeErrorKind::StackOverflow)
+    }
+
+    pub fn push(&mut self, value: Object) -> Result<(), RuntimeErrorKind> {
+        self.reserve(1)?;
+        self.slots.push(value);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<Object, RuntimeErrorKind> {
+        self.slots.pop().ok_or(RuntimeErrorKind::StackUnderflow)
+    }
+
+    pub fn get(&self, index: usize) -> Result<Object, RuntimeErrorKind> {
+        self.slots.get(index).copied().ok_or(RuntimeErrorKind::StackOutOfBounds { range: index..index + 1, len: self.len() })
+    }
+
+    pub fn set(&mut self, index: usize, value: Object) -> Result<(), RuntimeErrorKind> {
+        let len = self.len();
+        *self.slots.get_mut(index).ok_or(RuntimeErrorKind::StackOutOfBounds { range: index..index + 1, len })? = value;
+        Ok(())
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> Result<&[Object], RuntimeErrorKind> {
+        let len = self.len();
+        self.slots.get(range.to_owned()).ok_or(RuntimeErrorKind::StackOutOfBounds { range, len })
+    }
+
+    pub fn slice_mut(&mut self, range: Range<usize>) -> Result<&mut [Object], RuntimeErrorKind> {
+        let len = self.len();
+        self.slots.get_mut(range.to_owned()).ok_or(RuntimeErrorKind::StackOutOfBounds { range, len })
+    }
+
+    // New slots are filled with `TRUE_NULL`
+    pub fn resize(&mut self, len: usize) -> Result<(), RuntimeErrorKind> {
+        self.reserve(len.saturating_sub(self.len()))?;
+        self.slots.resize(len, Object::TRUE_NULL);
+        Ok(())
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.slots.truncate(len);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct CallFrame<'a> {
+    method: &'a CompiledMethod,
+    base: usize, // Index of `this` in the stack, it's followed by the locals and then the operands
+    pc: usize,
+    tail_calls: usize,
+}
+
+// stack[0] has to be `this`, followed by the arguments (or by already initialized locals, like in the repl)
+// Once the method returns, its locals are left on the stack
+pub fn run(ctx: &RunCtx, gc: &mut GC, stack: &mut VmStack, method: &CompiledMethod) -> Result<Object> {
+    let mut frames = Vec::new();
+
+    execute(ctx, gc, stack, method, &mut frames).map_err(|kind| {
+        // A deep recursion can leave millions of frames behind, so runs of identical ones are collapsed
+        // and only the innermost and outermost runs are kept
+        let class_of = |f: &CallFrame| stack.get(f.base).ok().map(|this| this.class());
+        let same = |a: &CallFrame, b: &CallFrame| ptr::eq(a.method, b.method) && a.pc == b.pc && a.tail_calls == b.tail_calls && class_of(a) == class_of(b);
+        let mut innermost: Vec<(CallFrame, usize)> = Vec::new();
+        let mut outermost: VecDeque<(CallFrame, usize)> = VecDeque::new();
+        let mut omitted = 0;
+        for f in frames.iter().rev() {
+            let last = if outermost.is_empty() { innermost.last_mut() } else { outermost.back_mut() };
+            if let Some((last, count)) = last {
+                if same(last, f) {
+                    *count += 1;
                     continue;
-                },
-                Pop => _ = pop!(),
+                }
+            }
+            if innermost.len() < AdvBacktrace::MAX_RUNS / 2 {
+                innermost.push((*f, 1));
+            } else {
+                outermost.push_back((*f, 1));
+                if outermost.len() > AdvBacktrace::MAX_RUNS / 2 {
+                    omitted += outermost.pop_front().unwrap().1;
+                }
             }
-            i += 1;
         }
-        assert_eq!(stack_pos, 0);
-    } else {
-        if *this == ctx.entrypoint {
-            match method.name.as_str() {
-                "'builtin:push_char'" => {
-                    let class_name = rest[0].class_name(&ctx.class_table);
-                    let stripped = class_name.strip_prefix('\'').unwrap().strip_suffix('\'').unwrap();
-                    let char = match stripped {
-                        "\\n" => '\n',
-                        "\\'" => '\'',
-                        "\0" => '\0',
-                        "\\\\" => '\\',
-                        c if c.len() == 1 => c.chars().nth(0).unwrap(),
-                        _ => panic!()
-                    };
-                    char_stack.push(char);
-                },
-                "'builtin:pop_char'" => {
-                    let char = char_stack.pop().unwrap_or('\0');
-                    let char_name = match char {
-                        '\n' => "\\n",
-                        '\'' => "\\'",
-                        '\0' => "\\0",
-                        '\\' => "\\\\",
-                        c => &c.to_string()
-                    };
-                    if let Ok(class) = ctx.class_table.get_class_id(&format!("'{char_name}'")) {
-                        return Ok(Object::new(ctx, gc, class));
-                    } else {
-                        return Ok(Object::null(ctx, gc));
+
+        let frames: Vec<_> = innermost.into_iter().chain(outermost).map(|(f, count)| (Frame {
+            class: class_of(&f).map_or_else(|| "?".into(), |class| ctx.class_table.classes[class].name),
+            method: f.method.name,
+            opcode: f.pc,
+            span: f.method.spans.get(f.pc).cloned(),
+            tail_calls: f.tail_calls,
+        }, count)).collect();
+
+        AdvError::Runtime(RuntimeError {
+            kind,
+            span: frames.first().and_then(|(f, _)| f.span.clone()),
+            backtrace: AdvBacktrace { frames, omitted },
+        })
+    })
+}
+
+fn execute<'a>(ctx: &'a RunCtx, gc: &mut GC, stack: &mut VmStack, method: &'a CompiledMethod, frames: &mut Vec<CallFrame<'a>>) -> Result<Object, RuntimeErrorKind> {
+    macro_rules! push {
+        ($value:expr) => {{
+            let value = $value;
+            stack.push(value)?;
+        }}
+    }
+
+    macro_rules! pop {
+        () => {
+            stack.pop()?
+        }
+    }
+
+    macro_rules! enter {
+        ($method:expr, $base:expr) => {{
+            let method = $method;
+            let base = $base;
+            frames.try_reserve(1).map_err(|_| RuntimeErrorKind::StackOverflow)?;
+            frames.push(CallFrame { method, base, pc: 0, tail_calls: 0 });
+            if method.body.is_some() {
+                stack.resize(base + 1 + method.locals_size)?;
+            }
+        }}
+    }
+
+    macro_rules! frame {
+        () => {
+            frames.last_mut().unwrap()
+        }
+    }
+
+    macro_rules! ret {
+        ($value:expr) => {{
+            let value = $value;
+            let frame = frames.pop().unwrap();
+            if frames.is_empty() {
+                return Ok(value);
+            }
+            stack.truncate(frame.base);
+            push!(value);
+            frame!().pc += 1;
+            continue;
+        }}
+    }
+
+    ensure!(stack.len() > method.params_count, RuntimeErrorKind::NotEnoughArguments { method: method.name });
+    enter!(method, 0);
+
+    loop {
+        let CallFrame { method, base, pc, .. } = *frames.last().unwrap();
+        let vars = base + 1;
+        let operands = vars + method.locals_size;
+
+        if let Some(ops) = &method.body {
+            if pc < ops.len() {
+                match &ops[pc] {
+                    New(class) => {
+                        // Everything that's alive is on the stack between instructions, so this is where collections happen
+                        if gc.should_collect() {
+                            gc.collect(stack.live());
+                        }
+                        push!(Object::new(ctx, gc, *class)?);
+                    },
+                    GetV(id) => {
+                        let value = stack.get(vars + id)?;
+                        ensure!(value != Object::TRUE_NULL, RuntimeErrorKind::UninitializedVariable);
+                        push!(value);
+                    },
+                    This => push!(stack.get(base)?),
+                    GetF(name, site) => {
+                        let obj = pop!();
+                        let index = cached_slot(&method.caches[*site], &ctx.cache_stats.get_field, obj.class(), || field_slot(ctx, method, obj.class(), *name))?;
+                        push!(gc.get(obj, index)?);
+                    },
+                    GetFI(index) => {
+                        let obj = pop!();
+                        push!(gc.get(obj, *index)?);
+                    },
+                    Call(selector, argc, site) => {
+                        let obj_i = stack.len().checked_sub(argc + 1).ok_or(RuntimeErrorKind::StackUnderflow)?;
+                        let obj = stack.get(obj_i)?;
+                        let class_name = ctx.class_table.classes[obj.class()].name;
+                        let name = ctx.class_table.selectors.name(*selector);
+                        let class = &ctx.classes[obj.class()];
+                        let index = cached_slot(&method.caches[*site], &ctx.cache_stats.call, obj.class(), || {
+                            let index = class.method_index(*selector).ok_or(RuntimeErrorKind::UndefinedMethod { class: class_name, method: name })?;
+                            let callee = &class.methods[index];
+                            ensure!(!callee.private || callee.unit == method.unit, RuntimeErrorKind::PrivateAccess { member: Member::Method, name, unit: callee.unit });
+                            Ok(index)
+                        })?;
+                        let method = &class.methods[index];
+                        ensure!(*argc == method.params_count, RuntimeErrorKind::ArgumentCount { class: class_name, method: name, expected: method.params_count, provided: *argc });
+
+                        enter!(method, obj_i);
+                        continue;
+                    },
+                    Is(range) => {
+                        let value = pop!().is(range);
+                        push!(Object::bool(ctx, gc, value)?);
+                    },
+                    Equals => {
+                        let a = pop!();
+                        let b = pop!();
+                        push!(Object::bool(ctx, gc, a == b)?);
                     }
-                },
-                "'builtin:write'" => {
-                    std::io::stdout().write_all(char_stack.as_bytes()).context("Failed to write to stdout")?;
-                    char_stack.clear();
-                },
-                "'builtin:read'" => {
-                    let mut inp = String::new();
-                    std::io::stdin().read_line(&mut inp).context("Failed to read from stdin")?;
-                    char_stack.clear();
-                    char_stack.extend(inp.chars().rev());
-                },
-                _ => bail!("Attempted to run a method without a body on an entrypoint class"),
+                    SetV(id) => {
+                        let value = pop!();
+                        stack.set(vars + id, value)?;
+                    },
+                    SetF(name, site) => {
+                        let value = pop!();
+                        let obj = pop!();
+
+                        let index = cached_slot(&method.caches[*site], &ctx.cache_stats.set_field, obj.class(), || field_slot(ctx, method, obj.class(), *name))?;
+                        gc.set(obj, index, value)?;
+                    },
+                    SetFI(index) => {
+                        let value = pop!();
+                        let obj = pop!();
+
+                        gc.set(obj, *index, value)?;
+                    },
+                    Return => {
+                        ensure!(stack.len() == operands + 1, RuntimeErrorKind::UnbalancedStack { method: method.name });
+                        ret!(pop!());
+                    },
+                    Jump(expected, location) => {
+                        if !expected ^ (pop!().is(&ctx.class_table.truth)) {
+                            frame!().pc = *location;
+                            continue;
+                        }
+                    },
+                    Recurse => {
+                        let args = operands + 1;
+                        let frame_slots = stack.slice_mut(vars..args + method.params_count)?;
+                        frame_slots.copy_within(args - vars.., 0);
+                        frame_slots[method.params_count..operands - vars].fill(Object::TRUE_NULL);
+                        stack.truncate(operands);
+                        let frame = frame!();
+                        frame.pc = 0;
+                        frame.tail_calls += 1;
+                        continue;
+                    },
+                    Pop => _ = pop!(),
+                }
+                frame!().pc += 1;
+            } else {
+                ensure!(stack.len() == operands, RuntimeErrorKind::UnbalancedStack { method: method.name });
+                ret!(Object::null(ctx, gc)?);
             }
         } else {
-            bail!("Attempted to run a method without a body");
+            let this = stack.get(base)?;
+            let builtin = ctx.builtins.get(&ctx.class_table, this.class(), method.name).ok_or(RuntimeErrorKind::MissingBody { method: method.name })?;
+            ret!(builtin(ctx, gc, this, stack.slice(vars..stack.len())?)?);
         }
     }
-    Ok(Object::null(ctx, gc))
 }
@@ -1,10 +1,12 @@
-use anyhow::{Result, Context, bail};
+use crate::symbol::*;
+use crate::syntax::*;
+use crate::error::*;
 
 use self::TokenKind::*;
 
 #[derive(PartialEq, Clone, Debug)]
 pub enum TokenKind {
-    Identifier(String, bool),
+    Identifier(Symbol, bool), // Strings keep their quotes, since that's how they're used as names
 
     BlockStart,
     BlockEnd,
@@ -17,6 +19,7 @@ pub enum TokenKind {
     Extends,
     Field,
     Method,
+    Private,
 
     Dot,
     Comma,
@@ -33,10 +36,7 @@ pub struct Token {
 }
 
 fn is_allowed_in_idents(c: char) -> bool {
-    c.is_alphanumeric() || match c {
-        '_' | '+' | '-' | '*' | '/' => true,
-        _ => false,
-    }
+    c.is_alphanumeric() || matches!(c, '_' | '+' | '-' | '*' | '/')
 }
 
 pub fn tokenize(file_name: &str, input: &str) -> Result<Vec<Token>> {
@@ -44,7 +44,7 @@ pub fn tokenize(file_name: &str, input: &str) -> Result<Vec<Token>> {
     let mut iter = input.chars().peekable();
 
     let mut line = 1;
-    let mut column = 1;
+    let mut column = 0;
 
     macro_rules! next {
         () => {
@@ -64,11 +64,12 @@ pub fn tokenize(file_name: &str, input: &str) -> Result<Vec<Token>> {
 
     macro_rules! require_next {
         () => {
-            next!().with_context(|| "{file_name}:{line}:{column}: Unexpected end of file")?
+            next!().ok_or_else(|| AdvError::Lex { span: Span::new(file_name, line, column), unexpected: None })?
         }
     }
 
     while let Some(c) = next!() {
+        let (start_line, start_column) = (line, column);
         let maybe_kind = match c {
             ':' => Some(BlockStart),
             '.' => Some(Dot),
@@ -84,6 +85,17 @@ pub fn tokenize(file_name: &str, input: &str) -> Result<Vec<Token>> {
                             next!();
                             string.push(s);
                         },
+                        // `::` separates modules from class names, a single ':' still starts a block
+                        Some(':') => {
+                            let mut ahead = iter.clone().skip(1);
+                            if ahead.next() == Some(':') && ahead.next().is_some_and(is_allowed_in_idents) {
+                                next!();
+                                next!();
+                                string.push_str("::");
+                            } else {
+                                break;
+                            }
+                        },
                         _ => break,
                     }
                 }
@@ -97,14 +109,18 @@ pub fn tokenize(file_name: &str, input: &str) -> Result<Vec<Token>> {
                     "extends" => Extends,
                     "field" => Field,
                     "method" => Method,
-                    _ => Identifier(string, false),
+                    "private" => Private,
+                    _ => Identifier(Symbol::intern(&string), false),
                 })
             },
             '\'' => {
-                let mut string = String::new();
+                let mut string = String::from('\'');
                 loop {
                     match require_next!() {
-                        '\'' => break,
+                        '\'' => {
+                            string.push('\'');
+                            break;
+                        },
                         s => {
                             string.push(s);
                             if s == '\\' {
@@ -113,20 +129,20 @@ pub fn tokenize(file_name: &str, input: &str) -> Result<Vec<Token>> {
                         },
                     }
                 }
-                Some(Identifier(string, true))
+                Some(Identifier(Symbol::intern(&string), true))
             },
             '#' => {
-                while iter.peek() != Some(&'\n') && iter.peek() != None {
+                while iter.peek() != Some(&'\n') && iter.peek().is_some() {
                     next!();
                 }
                 None
             },
             w if w.is_whitespace() => None,
-            c => bail!("{file_name}:{line}:{column}: Unexpected '{c}' character"),
+            c => bail!(AdvError::Lex { span: Span::new(file_name, line, column), unexpected: Some(c) }),
         };
 
         if let Some(kind) = maybe_kind {
-            result.push(Token { kind, line, column })
+            result.push(Token { kind, line: start_line, column: start_column })
         }
     }
 
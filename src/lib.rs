@@ -1,8 +1,18 @@
+pub mod symbol;
 pub mod syntax;
+pub mod error;
 pub mod lexer;
 pub mod parser;
+pub mod loader;
+pub mod namespace;
 pub mod stringifier;
 pub mod class_table;
 pub mod opcode;
+pub mod bytecode;
+pub mod disasm;
 pub mod interpreter;
+pub mod builtins;
 pub mod gc;
+pub mod vm;
+
+pub use vm::Vm;
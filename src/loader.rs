@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+
+use crate::syntax::*;
+use crate::lexer::*;
+use crate::parser::*;
+use crate::namespace::*;
+use crate::error::*;
+
+pub fn parse_file(path: &Path) -> Result<(Metadata, Vec<Class>)> {
+    let file_name = path.to_string_lossy();
+    let source = fs::read_to_string(path).map_err(|error| AdvError::Io { path: path.to_owned(), error })?;
+    parse(&file_name, tokenize(&file_name, &source)?)
+}
+
+// The directories given with `--lib-dir`, followed by the ones in ADV_PATH
+pub fn search_path(lib_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut result = lib_dirs.to_owned();
+    if let Some(paths) = env::var_os("ADV_PATH") {
+        result.extend(env::split_paths(&paths).filter(|p| !p.as_os_str().is_empty()));
+    }
+    result
+}
+
+struct Loader<'a> {
+    search_path: &'a [PathBuf],
+    loaded: HashSet<PathBuf>, // Canonical paths, so a file imported through different paths is only loaded once
+    chain: Vec<(PathBuf, PathBuf)>, // Canonical and original paths of the files being loaded, each one imports the next
+    files: Vec<(String, Metadata, Vec<Class>)>,
+}
+
+impl<'a> Loader<'a> {
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let canonical = fs::canonicalize(path).map_err(|error| AdvError::Io { path: path.to_owned(), error })?;
+        if let Some(i) = self.chain.iter().position(|(c, _)| *c == canonical) {
+            let chain = self.chain[i..].iter().map(|(_, p)| p.to_owned()).chain([path.to_owned()]).collect();
+            bail!(AdvError::ImportCycle { chain });
+        }
+        if !self.loaded.insert(canonical.to_owned()) {
+            return Ok(());
+        }
+
+        let (metadata, classes) = parse_file(path)?;
+        self.add(path, canonical, metadata, classes)
+    }
+
+    // Adds an already parsed file and loads its imports
+    fn add(&mut self, path: &Path, canonical: PathBuf, metadata: Metadata, classes: Vec<Class>) -> Result<()> {
+        let dependencies = metadata.dependencies.to_owned();
+        self.files.push((path.to_string_lossy().into_owned(), metadata, classes));
+
+        self.chain.push((canonical, path.to_owned()));
+        for dep in &dependencies {
+            let dep = self.resolve(path, dep)?;
+            self.load(&dep)?;
+        }
+        self.chain.pop();
+
+        Ok(())
+    }
+
+    // The directory of the importing file comes first, then the search path
+    // A path without a parent imports relative to the working directory instead of panicking
+    fn resolve(&self, importer: &Path, import: &str) -> Result<PathBuf> {
+        let dir = importer.parent().unwrap_or(Path::new("."));
+        let tried = [dir].into_iter().chain(self.search_path.iter().map(PathBuf::as_path)).map(|dir| dir.join(import)).collect::<Vec<_>>();
+        if let Some(path) = tried.iter().find(|p| p.is_file()) {
+            return Ok(path.to_owned());
+        }
+        bail!(AdvError::ImportNotFound { import: import.to_owned(), importer: importer.to_owned(), tried })
+    }
+}
+
+// Parses a file along with everything it imports, directly or through other files
+// Imports are looked up relative to the file that contains them and then in `search_path`
+// The metadata is the one of the root file, along with the singletons of every file it loaded
+// Class names are qualified with the module of the file that defines them
+pub fn load(path: &Path, search_path: &[PathBuf]) -> Result<(Metadata, Vec<Class>)> {
+    let (mut metadata, classes) = load_roots(&[path.to_owned()], &[], search_path)?;
+    Ok((metadata.pop().unwrap(), classes))
+}
+
+// Like `load`, but with several root files and sources, which are loaded in that order
+// Sources are `(name, source)` pairs, they import relative to their name like files do
+// Returns the metadata of every root, except for the ones that already got imported by an earlier root
+// The first one also gets the singletons of the files that were only imported
+pub fn load_roots(paths: &[PathBuf], sources: &[(String, String)], search_path: &[PathBuf]) -> Result<(Vec<Metadata>, Vec<Class>)> {
+    let mut loader = Loader {
+        search_path,
+        loaded: HashSet::new(),
+        chain: Vec::new(),
+        files: Vec::new(),
+    };
+    let mut roots = Vec::new();
+    for path in paths {
+        let index = loader.files.len();
+        loader.load(path)?;
+        if loader.files.len() > index {
+            roots.push(index);
+        }
+    }
+    for (name, source) in sources {
+        roots.push(loader.files.len());
+        let (metadata, classes) = parse(name, tokenize(name, source)?)?;
+        loader.add(Path::new(name), PathBuf::from(name), metadata, classes)?;
+    }
+    resolve_modules(&mut loader.files)?;
+
+    let mut metadata = roots.iter().map(|&i| loader.files[i].1.to_owned()).collect::<Vec<Metadata>>();
+    // Singletons apply to the whole program, and are already qualified with the module of the file that lists them
+    if let Some(first) = metadata.first_mut() {
+        first.singletons.extend(loader.files.iter().enumerate().filter(|(i, _)| !roots.contains(i)).flat_map(|(_, (_, m, _))| m.singletons.to_owned()));
+    }
+    let classes = loader.files.into_iter().flat_map(|(_, _, classes)| classes).collect();
+    Ok((metadata, classes))
+}
@@ -1,79 +1,133 @@
-use std::{io, env, path, fs};
+use std::{io, env, path, fs, process};
+use std::str::FromStr;
 use anyhow::{Result, Context, bail, ensure};
 
-use advrs::lexer::*;
 use advrs::syntax::*;
-use advrs::parser::*;
+use advrs::error::AdvError;
+use advrs::loader::*;
 use advrs::class_table::*;
 use advrs::opcode::*;
+use advrs::bytecode::*;
+use advrs::disasm::*;
 use advrs::interpreter::*;
 use advrs::gc::*;
 use advrs::stringifier::*;
+use advrs::Vm;
+
+mod repl;
+
+struct Args {
+    mode: String,
+    path: Option<String>,
+    output: Option<String>,
+    class: Option<String>,
+    method: Option<String>,
+    lib_dirs: Vec<path::PathBuf>,
+    cache_stats: bool,
+    stack_size: usize,
+    gc: GcConfig,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let usage = format!("Usage: {} [run|compile|disasm|merge|repl] [file] [-o output] [--class name] [--method name] [--lib-dir dir]... [--stack-size slots] [--cache-stats] [--gc-heap-size objects] [--gc-growth-factor factor] [--gc-max-heap-size objects] [--gc-verbosity 0-2]", env::args().next().unwrap_or("adv".to_string()));
+
+        fn value<T: FromStr>(iter: &mut impl Iterator<Item = String>, flag: &str) -> Result<T> {
+            let value = iter.next().with_context(|| format!("Expected a value after {flag}"))?;
+            value.parse().ok().with_context(|| format!("Invalid value for {flag}: '{value}'"))
+        }
+
+        let mut positional = Vec::new();
+        let mut output = None;
+        let mut class = None;
+        let mut method = None;
+        let mut lib_dirs = Vec::new();
+        let mut cache_stats = false;
+        let mut stack_size = VmStack::DEFAULT_MAX_SIZE;
+        let mut gc = GcConfig::from_env()?;
+
+        let mut iter = env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "-o" | "--output" => output = Some(value(&mut iter, &arg)?),
+                "--class" => class = Some(value(&mut iter, &arg)?),
+                "--method" => method = Some(value(&mut iter, &arg)?),
+                "--lib-dir" => lib_dirs.push(value(&mut iter, &arg)?),
+                "--cache-stats" => cache_stats = true,
+                "--stack-size" => stack_size = value(&mut iter, &arg)?,
+                "--gc-heap-size" => gc.initial_heap_size = value(&mut iter, &arg)?,
+                "--gc-growth-factor" => gc.growth_factor = value(&mut iter, &arg)?,
+                "--gc-max-heap-size" => gc.max_heap_size = Some(value(&mut iter, &arg)?),
+                "--gc-verbosity" => gc.verbosity = value(&mut iter, &arg)?,
+                _ => positional.push(arg),
+            }
+        }
+        gc.validate()?;
+
+        let mut positional = positional.into_iter();
+        Ok(Self {
+            mode: positional.next().with_context(|| usage.clone())?,
+            path: positional.next(),
+            output,
+            class,
+            method,
+            lib_dirs,
+            cache_stats,
+            stack_size,
+            gc,
+        })
+    }
+}
 
 fn main() -> Result<()> {
-    let builtin_classes = vec![
-        Class {
-            name: "Object".to_string(),
-            parent: None,
-            own_fields: vec![],
-            own_methods: vec![]
-        },
-        Class {
-            name: "Null".to_string(),
-            parent: None,
-            own_fields: vec![],
-            own_methods: vec![]
-        },
-    ];
+    let args = Args::parse()?;
+    let mode = args.mode;
+    let lib_path = search_path(&args.lib_dirs);
 
-    let path = if let Some(p) = env::args().nth(2) {
+    if mode == "repl" {
+        return repl::repl(args.path.as_deref().map(path::Path::new), &lib_path, args.stack_size, args.gc);
+    }
+
+    let path = if let Some(p) = args.path {
         p
     } else {
-        bail!("Usage: {} [run|merge] [file]", env::args().nth(0).unwrap_or("adv".to_string()));
+        bail!("Expected a file");
     };
     let path = path::Path::new(&path);
 
-    let (metadata, mut classes) = parse_file(&path)?;
-
-    for dep in &metadata.dependencies {
-        let (_, dclasses) = parse_file(&path.parent().unwrap().join(dep))?;
-        classes.extend(dclasses);
-    }
-
-    match env::args().nth(1).unwrap().as_str() {
+    match mode.as_str() {
         "run" => {
-            let all_classes = [builtin_classes, classes].concat();
-
-            let table = ClassTable::create(&all_classes)?;
-            let compiled = compile(&table)?;
-            let entrypoint = {
-                match &metadata.entrypoints[..] {
-                    [] => bail!("No entrypoint defined"),
-                    [id] => Ok::<_, anyhow::Error>(table.get_class_id(&id)?),
-                    list => {
-                        println!("Choose entrypoint:");
-                        for (i, ep) in list.iter().enumerate() {
-                            println!("{}) {}", i + 1, ep);
-                        }
-                        let mut inp = String::new();
-                        io::stdin().read_line(&mut inp)?;
-                        let n = inp.trim().parse::<usize>()?;
-                        ensure!(n >= 1 && n <= list.len(), "Inputted number was not in range");
-                        Ok(table.get_class_id(&list[n - 1])?)
-                    }
-                }
-            }.with_context(|| "Failed to find entrypoint")?;
-            let mut stack = vec![Object::TRUE_NULL; 1024];
-            let mut gc = GC::new(&stack[..] as *const [Object], 1024);
-            let ctx = RunCtx::new(&mut gc, table, compiled, entrypoint);
-            stack[0] = ctx.entrypoint;
-
-            run(&ctx, &mut gc, &mut String::new(), &mut stack, ctx.classes[entrypoint].methods.iter().find(|m| m.name == "main").with_context(|| "The entrypoint class doesn't have a main method")?).with_context(|| "Runtime error")?;
-
+            let (metadata, table, compiled) = load_program(path, &lib_path)?;
+            let entrypoint = choose_entrypoint(&metadata, &table)?;
+            let mut vm = Vm::builder().stack_size(args.stack_size).gc(args.gc).build_compiled(table, compiled, &metadata.singletons, entrypoint)?;
+            let this = vm.entrypoint();
+            let result = vm.call(&this, "main", &[]);
+            if args.cache_stats {
+                eprintln!("{}", vm.context().cache_stats);
+            }
+            match result {
+                Err(AdvError::Runtime(e)) => {
+                    eprintln!("Runtime error: {e}");
+                    process::exit(1);
+                },
+                result => _ = result?,
+            }
+        },
+        "compile" => {
+            let (metadata, table, compiled) = compile_program(path, &lib_path)?;
+            let output = args.output.map(path::PathBuf::from).unwrap_or_else(|| path.with_extension("advc"));
+            fs::write(&output, serialize(&metadata, &table, &compiled)).with_context(|| format!("Failed to write '{}'", output.display()))?;
+        },
+        "disasm" => {
+            let (_, table, compiled) = load_program(path, &lib_path)?;
+            print!("{}", disassemble(&table, &compiled, args.class.as_deref(), args.method.as_deref())?);
         },
         "merge" => {
+            let (metadata, classes) = load(path, &lib_path)?;
             let new_metadata = Metadata {
                 dependencies: vec![],
+                module: None,
+                uses: vec![],
                 ..metadata
             };
 
@@ -84,7 +138,43 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn parse_file(path: &path::Path) -> Result<(Metadata, Vec<Class>)> {
-    let file_name = path.to_str().with_context(|| "Failed to stringify path")?;
-    Ok(parse(file_name, tokenize(file_name, &fs::read_to_string(&path)?)?)?)
+fn choose_entrypoint(metadata: &Metadata, table: &ClassTable) -> Result<usize> {
+    match &metadata.entrypoints[..] {
+        [] => Err(AdvError::NoEntrypoint.into()),
+        [id] => Ok::<_, anyhow::Error>(table.get_class_id(id.as_str().into())?),
+        list => {
+            println!("Choose entrypoint:");
+            for (i, ep) in list.iter().enumerate() {
+                println!("{}) {}", i + 1, ep);
+            }
+            let mut inp = String::new();
+            io::stdin().read_line(&mut inp)?;
+            let n = inp.trim().parse::<usize>()?;
+            ensure!(n >= 1 && n <= list.len(), "Inputted number was not in range");
+            Ok(table.get_class_id(list[n - 1].as_str().into())?)
+        }
+    }.with_context(|| "Failed to find entrypoint")
+}
+
+// Compiled programs are loaded directly, anything else is treated as source
+fn load_program(path: &path::Path, lib_path: &[path::PathBuf]) -> Result<(Metadata, ClassTable, Vec<CompiledClass>)> {
+    if path.extension().is_some_and(|e| e == "advc") {
+        deserialize(&fs::read(path)?).with_context(|| format!("Failed to load '{}'", path.display()))
+    } else {
+        compile_program(path, lib_path)
+    }
 }
+
+fn compile_program(path: &path::Path, lib_path: &[path::PathBuf]) -> Result<(Metadata, ClassTable, Vec<CompiledClass>)> {
+    let (metadata, classes) = load(path, lib_path)?;
+    let all_classes = [builtin_classes(), classes].concat();
+
+    let table = ClassTable::create(&all_classes)?;
+    let mut warnings = Vec::new();
+    let compiled = compile(&table, &mut warnings);
+    for w in &warnings {
+        eprintln!("{w}");
+    }
+    Ok((metadata, table, compiled?))
+}
+
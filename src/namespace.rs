@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::syntax::*;
+use crate::symbol::*;
+use crate::error::*;
+
+// Qualified class names are written as `module::Class`, modules can be nested the same way
+pub const SEPARATOR: &str = "::";
+
+// `hiv::collections::List` belongs to `hiv::collections`, classes with unqualified names belong to the root module
+pub fn module_of(name: Symbol) -> Option<Symbol> {
+    name.as_str().rsplit_once(SEPARATOR).map(|(module, _)| Symbol::intern(module))
+}
+
+// Names that are already qualified are left as they are
+fn qualify(module: Option<&str>, name: Symbol) -> Symbol {
+    match module {
+        Some(module) if !name.as_str().contains(SEPARATOR) => Symbol::intern(&format!("{module}{SEPARATOR}{name}")),
+        _ => name,
+    }
+}
+
+// How class names are looked up inside of one file: `use` aliases first, then the file's own module, then the root module
+struct Scope<'a> {
+    module: Option<&'a str>,
+    aliases: HashMap<Symbol, Symbol>,
+    classes: &'a HashSet<Symbol>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(file: &str, metadata: &'a Metadata, classes: &'a HashSet<Symbol>) -> Result<Self> {
+        let mut aliases = HashMap::new();
+        for entry in &metadata.uses {
+            // Either `module::Class` or `module::Class as Alias`
+            let (target, alias) = match entry.split_once(" as ") {
+                Some((target, alias)) => (target.trim(), alias.trim()),
+                None => (entry.trim(), entry.rsplit_once(SEPARATOR).map_or("", |(_, name)| name)),
+            };
+            ensure!(target.contains(SEPARATOR) && !alias.is_empty() && !alias.contains(SEPARATOR), AdvError::InvalidMetadata(format!("{file}: '{entry}' is not a valid use entry, expected 'module::Class' or 'module::Class as Alias'")));
+            let target = Symbol::intern(target);
+            ensure!(classes.contains(&target), AdvError::InvalidMetadata(format!("{file}: Couldn't find class '{target}' to use")));
+            if let Some(previous) = aliases.insert(Symbol::intern(alias), target) {
+                ensure!(previous == target, AdvError::InvalidMetadata(format!("{file}: '{alias}' is used for both '{previous}' and '{target}'")));
+            }
+        }
+
+        Ok(Self {
+            module: metadata.module.as_deref(),
+            aliases,
+            classes,
+        })
+    }
+
+    // Names that don't refer to any class are left alone, they might be variables or missing classes that get reported later
+    fn resolve(&self, name: Symbol) -> Symbol {
+        if let Some(target) = self.aliases.get(&name) {
+            return *target;
+        }
+        let qualified = qualify(self.module, name);
+        if self.classes.contains(&qualified) {
+            qualified
+        } else {
+            name
+        }
+    }
+
+    fn expr(&self, locals: &HashSet<Symbol>, e: &mut Expression) {
+        match &mut e.kind {
+            ExpressionKind::Get(name) => {
+                if !locals.contains(name) {
+                    *name = self.resolve(*name);
+                }
+            },
+            ExpressionKind::GetF(obj, _) => self.expr(locals, obj),
+            ExpressionKind::Is(obj, class) => {
+                self.expr(locals, obj);
+                *class = self.resolve(*class);
+            },
+            ExpressionKind::Call(obj, _, args) => {
+                self.expr(locals, obj);
+                args.iter_mut().for_each(|a| self.expr(locals, a));
+            },
+            ExpressionKind::Equals(a, b) => {
+                self.expr(locals, a);
+                self.expr(locals, b);
+            },
+        }
+    }
+
+    fn block(&self, locals: &HashSet<Symbol>, b: &mut [Statement]) {
+        for s in b {
+            match &mut s.kind {
+                StatementKind::SetV(_, value) | StatementKind::Return(value) => self.expr(locals, value),
+                StatementKind::SetF(obj, _, value) => {
+                    self.expr(locals, obj);
+                    self.expr(locals, value);
+                },
+                StatementKind::Call(obj, _, args) => {
+                    self.expr(locals, obj);
+                    args.iter_mut().for_each(|a| self.expr(locals, a));
+                },
+                StatementKind::If(condition, body) | StatementKind::While(condition, body) => {
+                    self.expr(locals, condition);
+                    self.block(locals, body);
+                },
+            }
+        }
+    }
+}
+
+// Every variable that's assigned anywhere in the method, they take precedence over classes
+fn find_locals(method: &Method) -> HashSet<Symbol> {
+    fn block(locals: &mut HashSet<Symbol>, b: &[Statement]) {
+        for s in b {
+            match &s.kind {
+                StatementKind::SetV(name, _) => _ = locals.insert(*name),
+                StatementKind::If(_, body) | StatementKind::While(_, body) => block(locals, body),
+                _ => (),
+            }
+        }
+    }
+
+    let mut locals = method.params.iter().copied().chain(["this".into()]).collect();
+    if let Some(body) = &method.body {
+        block(&mut locals, body);
+    }
+    locals
+}
+
+// Puts the classes of files with a `module` metadata entry into that module and replaces every class name with its qualified version
+// This includes parents, `is` checks, class references in method bodies and the entrypoints and singletons of the metadata
+pub fn resolve_modules(files: &mut [(String, Metadata, Vec<Class>)]) -> Result<()> {
+    for (_, metadata, classes) in files.iter_mut() {
+        for c in classes {
+            c.name = qualify(metadata.module.as_deref(), c.name);
+            c.module = module_of(c.name);
+        }
+    }
+    let names = files.iter().flat_map(|(_, _, classes)| classes.iter().map(|c| c.name)).collect::<HashSet<_>>();
+
+    for (file, metadata, classes) in files.iter_mut() {
+        let scope = Scope::new(file, metadata, &names)?;
+        for c in classes.iter_mut() {
+            c.parent = c.parent.map(|p| scope.resolve(p));
+            for m in &mut c.own_methods {
+                let locals = find_locals(m);
+                if let Some(body) = &mut m.body {
+                    scope.block(&locals, body);
+                }
+            }
+        }
+
+        let entrypoints = metadata.entrypoints.iter().map(|e| scope.resolve(e.as_str().into()).to_string()).collect();
+        let singletons = metadata.singletons.iter().map(|s| if s == "*" { s.to_owned() } else { scope.resolve(s.as_str().into()).to_string() }).collect();
+        metadata.entrypoints = entrypoints;
+        metadata.singletons = singletons;
+    }
+    Ok(())
+}
@@ -1,8 +1,10 @@
 use std::rc::Rc;
-
-use anyhow::{Result, Context, Ok, bail};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 
 use crate::syntax::*;
+use crate::symbol::*;
+use crate::error::*;
 use crate::class_table::*;
 
 use self::OpCode::*;
@@ -12,14 +14,14 @@ pub enum OpCode {
     New(usize),
     GetV(usize),
     This,
-    GetF(String),
+    GetF(Symbol, usize), // Field name and inline cache site
     GetFI(usize),
-    Call(String, usize), // Method name and arg count
+    Call(usize, usize, usize), // Selector, arg count and inline cache site
     Is(TypeRange),
     Equals,
 
     SetV(usize),
-    SetF(String),
+    SetF(Symbol, usize),
     SetFI(usize),
     Return,
     Jump(bool, usize),
@@ -29,134 +31,261 @@ pub enum OpCode {
 }
 
 impl OpCode {
-    pub fn stack_diff(&self) -> isize {
+    // `params_count` is the one of the method containing the opcode, `Recurse` takes the same arguments as the call it replaced
+    pub fn stack_diff(&self, params_count: usize) -> isize {
         match &self {
             New(_) => 1,
             GetV(_) => 1,
             This => 1,
-            GetF(_) | GetFI(_) => 0,
-            Call(_, argc) => 1 - *argc as isize - 1,
+            GetF(_, _) | GetFI(_) => 0,
+            Call(_, argc, _) => 1 - *argc as isize - 1,
             Is(_) => 0,
             Equals => -1,
             SetV(_) => -1,
-            SetF(_) | SetFI(_) => -1,
+            SetF(_, _) | SetFI(_) => -1,
             Return => -1,
             Jump(_, _) => -1,
-            Recurse => panic!(),
+            Recurse => -(params_count as isize),
             Pop => -1,
         }
     }
 }
 
+pub const INLINE_CACHE_SIZE: usize = 4;
+
+// Remembers what a `Call`, `GetF` or `SetF` resolved to for the last few receiver classes
+// The slot is an index into either the methods or the fields of the class
+#[derive(Clone, Debug)]
+pub struct InlineCache {
+    entries: [Cell<(usize, usize)>; INLINE_CACHE_SIZE], // Receiver class and slot, unused entries have a class of usize::MAX
+    next: Cell<usize>, // The entry that gets replaced on the next miss
+}
+
+impl InlineCache {
+    pub fn lookup(&self, class: usize) -> Option<usize> {
+        self.entries.iter().map(Cell::get).find(|e| e.0 == class).map(|e| e.1)
+    }
+
+    pub fn insert(&self, class: usize, slot: usize) {
+        let i = self.next.get();
+        self.entries[i].set((class, slot));
+        self.next.set((i + 1) % INLINE_CACHE_SIZE);
+    }
+}
+
+impl Default for InlineCache {
+    fn default() -> Self {
+        Self {
+            entries: std::array::from_fn(|_| Cell::new((usize::MAX, 0))),
+            next: Cell::new(0),
+        }
+    }
+}
+
+impl PartialEq for InlineCache {
+    fn eq(&self, _: &Self) -> bool {
+        true // Caches are only runtime state, they don't change what a method does
+    }
+}
+
+// Gives every `Call`, `GetF` and `SetF` the next site, in the order they appear, and returns how many there are
+// Sites aren't stored in compiled files, since they can always be numbered again like this
+pub fn number_cache_sites(body: &mut [OpCode]) -> usize {
+    let mut count = 0;
+    for op in body {
+        if let Call(_, _, site) | GetF(_, site) | SetF(_, site) = op {
+            *site = count;
+            count += 1;
+        }
+    }
+    count
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub struct CompiledMethod {
-    pub name: String,
+    pub name: Symbol,
     pub body: Option<Vec<OpCode>>,
+    pub spans: Vec<Span>, // Source location of each opcode in the body
+    pub caches: Vec<InlineCache>, // Indexed by the sites of the opcodes that look something up by name
     pub params_count: usize,
     pub locals_size: usize,
+    pub unit: Symbol, // Of the class that defines the method, what it's allowed to use is checked against it at runtime
+    pub private: bool,
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct CompiledClass {
-    pub fields: Vec<String>,
+    pub fields: Vec<Symbol>,
     pub methods: Vec<Rc<CompiledMethod>>,
+    pub vtable: Vec<Option<usize>>, // Indexes `methods` by selector
+}
+
+impl CompiledClass {
+    pub fn new(class_table: &ClassTable, fields: Vec<Symbol>, methods: Vec<Rc<CompiledMethod>>) -> Result<Self> {
+        let mut vtable = vec![None; class_table.selectors.len()];
+        for (i, m) in methods.iter().enumerate() {
+            let selector = class_table.selectors.get(m.name).ok_or_else(|| AdvError::Verify { span: None, class: None, method: m.name, message: "The method doesn't have a selector".to_string() })?;
+            vtable[selector] = Some(i);
+        }
+        Ok(Self {
+            fields,
+            methods,
+            vtable,
+        })
+    }
+
+    pub fn method_index(&self, selector: usize) -> Option<usize> {
+        self.vtable.get(selector).copied().flatten()
+    }
+
+    pub fn lookup(&self, selector: usize) -> Option<&Rc<CompiledMethod>> {
+        self.method_index(selector).map(|i| &self.methods[i])
+    }
+}
+
+fn compile_error(span: &Span, message: String) -> AdvError {
+    AdvError::Compile { span: span.to_owned(), class: None, method: None, message }
+}
+
+fn selector(class_table: &ClassTable, name: Symbol, span: &Span) -> Result<usize> {
+    class_table.selectors.get(name).ok_or_else(|| compile_error(span, format!("No class defines a method named '{name}'")))
+}
+
+// Accesses through `this` can be checked against the member that they actually refer to
+fn is_this(expr: &Expression) -> bool {
+    matches!(&expr.kind, ExpressionKind::Get(name) if name == "this")
+}
+
+struct OpCodeBuilder {
+    ops: Vec<OpCode>,
+    spans: Vec<Span>,
+    warnings: Vec<Warning>,
+}
+
+impl OpCodeBuilder {
+    fn new() -> Self {
+        Self {
+            ops: Vec::new(),
+            spans: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, op: OpCode, span: &Span) {
+        self.ops.push(op);
+        self.spans.push(span.to_owned());
+    }
+
+    fn len(&self) -> usize {
+        self.ops.len()
+    }
 }
 
-fn compile_expr(class_table: &ClassTable, result: &mut Vec<OpCode>, locals: &Vec<String>, expr: &Expression) -> Result<()> {
-    match expr {
-        Expression::Get(name) if name == "this" => result.push(This),
-        Expression::Get(name) if locals.contains(name) => result.push(GetV(locals.iter().position(|l| l == name).unwrap())),
-        Expression::Get(name) if class_table.map.contains_key(name) => result.push(New(class_table.map.get(name).unwrap().0)),
-        Expression::Get(name) => bail!("Couldn't find a class or variable named '{name}'"),
-        Expression::GetF(obj, name) => {
-            compile_expr(class_table, result, locals, obj)?;
-            result.push(GetF(name.to_owned()));
+fn compile_expr(class_table: &ClassTable, class: usize, result: &mut OpCodeBuilder, locals: &Vec<Symbol>, expr: &Expression) -> Result<()> {
+    let span = &expr.span;
+    match &expr.kind {
+        ExpressionKind::Get(name) if name == "this" => result.push(This, span),
+        ExpressionKind::Get(name) if locals.contains(name) => result.push(GetV(locals.iter().position(|l| l == name).unwrap()), span),
+        ExpressionKind::Get(name) if class_table.map.contains_key(name) => {
+            class_table.check_access(class, Member::Class, *name, false, span)?;
+            result.push(New(class_table.map.get(name).unwrap().0), span);
         },
-        Expression::Call(obj, name, args) => {
-            compile_expr(class_table, result, locals, obj)?;
+        ExpressionKind::Get(name) => bail!(compile_error(span, format!("Couldn't find a class or variable named '{name}'"))),
+        ExpressionKind::GetF(obj, name) => {
+            class_table.check_access(class, Member::Field, *name, is_this(obj), span)?;
+            compile_expr(class_table, class, result, locals, obj)?;
+            result.push(GetF(name.to_owned(), 0), span);
+        },
+        ExpressionKind::Call(obj, name, args) => {
+            class_table.check_access(class, Member::Method, *name, is_this(obj), span)?;
+            compile_expr(class_table, class, result, locals, obj)?;
             for a in args {
-                compile_expr(class_table, result, locals, a)?;
+                compile_expr(class_table, class, result, locals, a)?;
             }
-            result.push(Call(name.to_owned(), args.len()));
+            result.push(Call(selector(class_table, *name, span)?, args.len(), 0), span);
         },
-        Expression::Is(obj, class) => {
-           if let Some(range) = class_table.map.get(class) {
-                compile_expr(class_table, result, locals, obj)?;
-                result.push(Is(range.to_owned()));
+        ExpressionKind::Is(obj, target) => {
+           if let Some(range) = class_table.map.get(target) {
+                class_table.check_access(class, Member::Class, *target, false, span)?;
+                compile_expr(class_table, class, result, locals, obj)?;
+                result.push(Is(range.to_owned()), span);
            } else {
-                eprintln!("Warning: Couldn't find a class named '{class}', 'is' check will be ignored");
-                result.push(New(class_table.lie.0))
+                result.warnings.push(Warning { span: span.to_owned(), message: format!("Couldn't find a class named '{target}', 'is' check will be ignored") });
+                result.push(New(class_table.lie.0), span)
            }
         },
-        Expression::Equals(a, b) => {
-            compile_expr(class_table, result, locals, a)?;
-            compile_expr(class_table, result, locals, b)?;
-            result.push(Equals);
+        ExpressionKind::Equals(a, b) => {
+            compile_expr(class_table, class, result, locals, a)?;
+            compile_expr(class_table, class, result, locals, b)?;
+            result.push(Equals, span);
         },
     }
     Ok(())
 }
 
-fn compile_block(class_table: &ClassTable, result: &mut Vec<OpCode>, locals: &mut Vec<String>, block: &Vec<Statement>) -> Result<()> {
+fn compile_block(class_table: &ClassTable, class: usize, result: &mut OpCodeBuilder, locals: &mut Vec<Symbol>, block: &Vec<Statement>) -> Result<()> {
     for stmt in block {
-        match stmt {
-            Statement::SetV(name, value) => {
+        let span = &stmt.span;
+        match &stmt.kind {
+            StatementKind::SetV(name, value) => {
                 let id = if let Some(id) = locals.iter().position(|l| l == name) {
                     id
                 } else {
                     locals.push(name.to_owned());
                     locals.len() - 1
                 };
-                compile_expr(class_table, result, locals, value)?;
-                result.push(SetV(id));
+                compile_expr(class_table, class, result, locals, value)?;
+                result.push(SetV(id), span);
             },
-            Statement::SetF(obj, name, value) => {
-                compile_expr(class_table, result, locals, obj)?;
-                compile_expr(class_table, result, locals, value)?;
-                result.push(SetF(name.to_owned()));
+            StatementKind::SetF(obj, name, value) => {
+                class_table.check_access(class, Member::Field, *name, is_this(obj), span)?;
+                compile_expr(class_table, class, result, locals, obj)?;
+                compile_expr(class_table, class, result, locals, value)?;
+                result.push(SetF(name.to_owned(), 0), span);
             },
-            Statement::Call(obj, name, args) => {
-                compile_expr(class_table, result, locals, obj)?;
+            StatementKind::Call(obj, name, args) => {
+                class_table.check_access(class, Member::Method, *name, is_this(obj), span)?;
+                compile_expr(class_table, class, result, locals, obj)?;
                 for a in args {
-                    compile_expr(class_table, result, locals, a)?;
+                    compile_expr(class_table, class, result, locals, a)?;
                 }
-                result.push(Call(name.to_owned(), args.len()));
-                result.push(Pop);
+                result.push(Call(selector(class_table, *name, span)?, args.len(), 0), span);
+                result.push(Pop, span);
             },
-            Statement::Return(value) => {
-                compile_expr(class_table, result, locals, value)?;
-                result.push(Return);
+            StatementKind::Return(value) => {
+                compile_expr(class_table, class, result, locals, value)?;
+                result.push(Return, span);
             },
-            Statement::If(condition, block) => {
-                compile_expr(class_table, result, locals, condition)?;
+            StatementKind::If(condition, block) => {
+                compile_expr(class_table, class, result, locals, condition)?;
                 let jump_index = result.len();
-                result.push(Pop);
-                compile_block(class_table, result, locals, block)?;
-                result[jump_index] = Jump(false, result.len())
+                result.push(Pop, span);
+                compile_block(class_table, class, result, locals, block)?;
+                result.ops[jump_index] = Jump(false, result.len())
             },
-            Statement::While(condition, block) => {
-                compile_expr(class_table, result, locals, condition)?;
+            StatementKind::While(condition, block) => {
+                compile_expr(class_table, class, result, locals, condition)?;
                 let jump_index = result.len();
-                result.push(Pop);
-                compile_block(class_table, result, locals, block)?;
-                compile_expr(class_table, result, locals, condition)?;
-                result.push(Jump(true, jump_index + 1));
-                result[jump_index] = Jump(false, result.len())
+                result.push(Pop, span);
+                compile_block(class_table, class, result, locals, block)?;
+                compile_expr(class_table, class, result, locals, condition)?;
+                result.push(Jump(true, jump_index + 1), span);
+                result.ops[jump_index] = Jump(false, result.len())
             },
         }
     }
     Ok(())
 }
 
-fn optimize_body(this_fields: &Vec<String>, method: &Method, compiled_body: &mut Vec<OpCode>) -> Result<()> {
-    fn tail_call_optimization(method: &Method, compiled_body: &mut Vec<OpCode>, tail: usize) {
-        if let Call(name, argc) = &compiled_body[tail] {
-            if name == &method.name && argc == &method.params.len() {
+fn optimize_body(class_table: &ClassTable, this_fields: &[Symbol], method: &Method, compiled_body: &mut [OpCode], spans: &[Span]) -> Result<()> {
+    fn tail_call_optimization(class_table: &ClassTable, method: &Method, compiled_body: &mut [OpCode], tail: usize) {
+        if let Call(selector, argc, _) = &compiled_body[tail] {
+            if class_table.selectors.get(method.name) == Some(*selector) && argc == &method.params.len() {
                 let mut stack_diff = 0;
                 let mut j = tail;
                 while stack_diff != *argc as isize {
                     j -= 1;
-                    stack_diff += compiled_body[j].stack_diff();
+                    stack_diff += compiled_body[j].stack_diff(method.params.len());
                 }
                 
                 if compiled_body[j - 1] == This {
@@ -168,71 +297,230 @@ fn optimize_body(this_fields: &Vec<String>, method: &Method, compiled_body: &mut
 
     for i in 0..compiled_body.len() {
         match &compiled_body[i] {
-            GetF(name) => {
-                if compiled_body[i - 1] == This {
-                    compiled_body[i] = GetFI(this_fields.iter().position(|f| f == name).with_context(|| "No such field")?)
-                }
+            GetF(name, _) if compiled_body[i - 1] == This => {
+                compiled_body[i] = GetFI(this_fields.iter().position(|f| f == name).ok_or_else(|| compile_error(&spans[i], format!("No such field {name}")))?)
             },
-            SetF(name) => {
+            SetF(name, _) => {
                 let mut stack_diff = 0;
                 let mut j = i;
                 while stack_diff != 1 {
                     j -= 1;
-                    stack_diff += compiled_body[j].stack_diff();
+                    stack_diff += compiled_body[j].stack_diff(method.params.len());
                 }
 
                 if compiled_body[j - 1] == This {
-                    compiled_body[i] = SetFI(this_fields.iter().position(|f| f == name).with_context(|| format!("No such field {name}"))?)
+                    compiled_body[i] = SetFI(this_fields.iter().position(|f| f == name).ok_or_else(|| compile_error(&spans[i], format!("No such field {name}")))?)
                 }
             },
-            Return => tail_call_optimization(method, compiled_body, i - 1),
-            Pop if i == compiled_body.len() - 1 => tail_call_optimization(method, compiled_body, i - 1),
+            Return => tail_call_optimization(class_table, method, compiled_body, i - 1),
+            Pop if i == compiled_body.len() - 1 => tail_call_optimization(class_table, method, compiled_body, i - 1),
             _ => (),
         }
     }
     Ok(())
 }
 
-fn compile_method(class_table: &ClassTable, method: &Method, this_fields: &Vec<String>) -> Result<CompiledMethod> {
+// Checks that the body can't misbehave at runtime: every path has to keep the stack balanced and all operands have to be in range
+// `this_fields` are the fields of the class that defines the method, subclasses can only add more of them
+pub fn verify_method(class_table: &ClassTable, this_fields: &[Symbol], method: &CompiledMethod) -> Result<()> {
+    let Some(body) = &method.body else {
+        return Ok(());
+    };
+    macro_rules! check {
+        ($cond:expr, $span:expr, $($arg:tt)*) => {
+            ensure!($cond, AdvError::Verify { span: $span, class: None, method: method.name, message: format!($($arg)*) })
+        };
+    }
+
+    check!(method.spans.len() == body.len(), None, "There are {} spans for {} opcodes", method.spans.len(), body.len());
+    let sites = body.iter().filter_map(|op| if let Call(_, _, site) | GetF(_, site) | SetF(_, site) = op { Some(*site) } else { None }).collect::<Vec<_>>();
+    check!(sites.iter().copied().eq(0..sites.len()), None, "Inline cache sites aren't numbered in order");
+    check!(method.caches.len() == sites.len(), None, "There are {} inline caches for {} sites", method.caches.len(), sites.len());
+    check!(method.params_count <= method.locals_size, None, "There are more parameters than locals");
+
+    // The operand stack is tracked abstractly: each entry only records whether it's `this`, since fields can only be accessed by index through it
+    let class_count = class_table.classes.len();
+    let mut states: Vec<Option<Vec<bool>>> = vec![None; body.len() + 1]; // The state before each opcode, the last entry is the end of the body
+    let mut worklist = vec![(0, vec![])];
+
+    while let Some((mut pc, mut stack)) = worklist.pop() {
+        loop {
+            if let Some(expected) = &states[pc] {
+                check!(*expected == stack, method.spans.get(pc).or(method.spans.last()).cloned(), "Inconsistent stack at opcode {pc}, its depth is either {} or {}", expected.len(), stack.len());
+                break;
+            }
+            states[pc] = Some(stack.to_owned());
+
+            let Some(op) = body.get(pc) else {
+                check!(stack.is_empty(), Some(method.spans[pc - 1].to_owned()), "Unbalanced stack at the end of the method");
+                break;
+            };
+            let span = &method.spans[pc];
+            let depth = stack.len();
+            let needed = match op {
+                New(_) | GetV(_) | This => 0,
+                GetF(_, _) | GetFI(_) | Is(_) | SetV(_) | Jump(_, _) | Pop | Return => 1,
+                SetF(_, _) | SetFI(_) | Equals => 2,
+                Call(_, argc, _) => argc + 1,
+                Recurse => method.params_count + 1,
+            };
+            check!(depth >= needed, Some(span.to_owned()), "Stack underflow at opcode {pc} ({op:?})");
+            let receiver_is_this = needed > 0 && stack[depth - needed];
+
+            match op {
+                New(class) => check!(*class < class_count, Some(span.to_owned()), "Opcode {pc} creates an instance of class {class}, which doesn't exist"),
+                GetV(id) | SetV(id) => check!(*id < method.locals_size, Some(span.to_owned()), "Opcode {pc} uses variable {id}, but there are only {} of them", method.locals_size),
+                GetFI(index) | SetFI(index) => {
+                    check!(*index < this_fields.len(), Some(span.to_owned()), "Opcode {pc} uses field {index}, but the class only has {}", this_fields.len());
+                    check!(receiver_is_this, Some(span.to_owned()), "Opcode {pc} accesses a field by index on something other than 'this'");
+                },
+                Call(selector, _, _) => check!(*selector < class_table.selectors.len(), Some(span.to_owned()), "Opcode {pc} calls selector {selector}, which doesn't exist"),
+                Is(range) => check!(range.0 <= range.1 && range.1 <= class_count, Some(span.to_owned()), "Opcode {pc} checks against an invalid type range {}..{}", range.0, range.1),
+                Jump(_, location) => check!(*location <= body.len(), Some(span.to_owned()), "Opcode {pc} jumps to {location}, outside of the method"),
+                Return => check!(depth == 1, Some(span.to_owned()), "Unbalanced stack at return (depth {depth})"),
+                Recurse => {
+                    check!(depth == needed, Some(span.to_owned()), "Opcode {pc} is a tail call with a stack depth of {depth}, expected {needed}");
+                    check!(receiver_is_this, Some(span.to_owned()), "Opcode {pc} is a tail call on something other than 'this'");
+                },
+                _ => (),
+            }
+
+            stack.truncate(depth - needed);
+            match op {
+                This => stack.push(true),
+                New(_) | GetV(_) | GetF(_, _) | GetFI(_) | Call(_, _, _) | Is(_) | Equals => stack.push(false),
+                Jump(_, location) => worklist.push((*location, stack.to_owned())),
+                Return => break,
+                Recurse => {
+                    worklist.push((0, vec![]));
+                    break;
+                },
+                _ => (),
+            }
+            pc += 1;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn verify(class_table: &ClassTable, classes: &[CompiledClass]) -> Result<()> {
+    ensure!(classes.len() == class_table.classes.len(), AdvError::Bytecode(format!("There are {} compiled classes for {} classes", classes.len(), class_table.classes.len())));
+
+    // Inherited methods are shared, parents come before their children so the first class to have a method is the one that defines it
+    let mut verified = HashSet::new();
+    for (c, compiled) in class_table.classes.iter().zip(classes) {
+        for m in &compiled.methods {
+            if verified.insert(Rc::as_ptr(m)) {
+                verify_method(class_table, &compiled.fields, m).map_err(|e| e.in_method(c.name, m.name))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn compile_method(class_table: &ClassTable, class: usize, method: &Method, this_fields: &[Symbol], warnings: &mut Vec<Warning>) -> Result<CompiledMethod> {
+    compile_method_with_locals(class_table, class, method, this_fields, &mut method.params.to_owned(), warnings)
+}
+
+// Locals declared by the body get appended to `locals`, so they can be reused between compilations (used by the repl)
+// `class` is the one that defines the method, it decides which private classes and members the body can use
+pub fn compile_method_with_locals(class_table: &ClassTable, class: usize, method: &Method, this_fields: &[Symbol], locals: &mut Vec<Symbol>, warnings: &mut Vec<Warning>) -> Result<CompiledMethod> {
     if let Some(body) = &method.body {
-        let mut locals = method.params.to_owned();
-        let mut compiled_body = Vec::new();
-        compile_block(class_table, &mut compiled_body, &mut locals, &body)?;
-        optimize_body(this_fields, method, &mut compiled_body)?;
-        Ok(CompiledMethod {
+        let mut compiled_body = OpCodeBuilder::new();
+        compile_block(class_table, class, &mut compiled_body, locals, body)?;
+        optimize_body(class_table, this_fields, method, &mut compiled_body.ops, &compiled_body.spans)?;
+        warnings.append(&mut compiled_body.warnings);
+        let sites = number_cache_sites(&mut compiled_body.ops);
+        let result = CompiledMethod {
             name: method.name.to_owned(),
-            body: Some(compiled_body),
+            caches: vec![InlineCache::default(); sites],
+            body: Some(compiled_body.ops),
+            spans: compiled_body.spans,
             params_count: method.params.len(),
             locals_size: locals.len(),
-        })
+            unit: unit_of(&class_table.classes[class]),
+            private: method.private,
+        };
+        verify_method(class_table, this_fields, &result)?;
+        Ok(result)
     } else {
         Ok(CompiledMethod {
             name: method.name.to_owned(),
             body: None,
+            spans: vec![],
+            caches: vec![],
             params_count: method.params.len(),
             locals_size: 0,
+            unit: unit_of(&class_table.classes[class]),
+            private: method.private,
         })
     }
 }
 
-fn inherit<T: ToOwned>(parent: &Vec<T>, child: &Vec<T>, get_name: fn(&T) -> &str) -> Vec<T::Owned> {
+fn inherit<T: ToOwned>(parent: &[T], child: &[T], get_name: fn(&T) -> Symbol) -> Vec<T::Owned> {
     parent.iter().map(|p| if let Some(c) = child.iter().find(|c| get_name(c) == get_name(p)) { c } else { p }).chain(child.iter().filter(|c| !parent.iter().any(|p| get_name(p) == get_name(c)))).map(ToOwned::to_owned).collect()
 }
 
-pub fn compile(class_table: &ClassTable) -> Result<Vec<CompiledClass>> {
+// Warnings get appended to `warnings`, even if the compilation fails later on
+pub fn compile(class_table: &ClassTable, warnings: &mut Vec<Warning>) -> Result<Vec<CompiledClass>> {
+    compile_missing(class_table, vec![None; class_table.classes.len()], warnings)
+}
+
+// Like `compile`, but the classes that already have a compiled version in `done` (indexed by class id) are kept as they are
+pub fn compile_missing(class_table: &ClassTable, mut done: Vec<Option<CompiledClass>>, warnings: &mut Vec<Warning>) -> Result<Vec<CompiledClass>> {
     let mut result: Vec<CompiledClass> = Vec::with_capacity(class_table.classes.len());
     
-    for c in &class_table.classes {
-        let parent = c.parent.as_ref().map(|p| &result[class_table.get_class_id(p).unwrap()]);
+    for (i, c) in class_table.classes.iter().enumerate() {
+        if let Some(compiled) = done[i].take() {
+            result.push(compiled);
+            continue;
+        }
+        let parent = c.parent.as_ref().map(|p| &result[class_table.get_class_id(*p).unwrap()]);
 
-        let fields = if let Some(p) = parent { inherit(&p.fields, &c.own_fields, |f| f) } else { c.own_fields.to_owned() };
-        let my_methods = c.own_methods.iter().map(|m| Ok(Rc::new(compile_method(class_table, m, &fields).with_context(|| format!("Failed to compile method '{}.{}'", c.name, m.name))?))).collect::<Result<Vec<_>, _>>()?;
-        let methods = if let Some(p) = parent { inherit(&p.methods, &my_methods, |m| &m.name) } else { my_methods };
+        let fields = if let Some(p) = parent { inherit(&p.fields, &c.own_fields, |f| *f) } else { c.own_fields.to_owned() };
+        let my_methods = c.own_methods.iter().map(|m| compile_method(class_table, i, m, &fields, warnings).map(Rc::new).map_err(|e| e.in_method(c.name, m.name))).collect::<Result<Vec<_>>>()?;
+        let methods = if let Some(p) = parent { inherit(&p.methods, &my_methods, |m| m.name) } else { my_methods };
 
-        result.push(CompiledClass {
-            fields,
-            methods,
-        });
+        result.push(CompiledClass::new(class_table, fields, methods)?);
+    }
+
+    Ok(result)
+}
+
+// Moves classes compiled against `from` over to `to`, which has to contain every class and selector of `from` (used by the repl)
+// Class ids, type ranges and selectors are looked up again by name, and the caches start out empty
+// The result is indexed by the ids in `to`, with `None` for the classes that only exist there
+pub fn relocate(from: &ClassTable, to: &ClassTable, classes: &[CompiledClass]) -> Result<Vec<Option<CompiledClass>>> {
+    let class_id = |class: usize| to.get_class_id(from.classes[class].name);
+    let mut relocated: HashMap<*const CompiledMethod, Rc<CompiledMethod>> = HashMap::new();
+    let mut result = vec![None; to.classes.len()];
+
+    // Every old method stays alive until the end, so their addresses keep telling them apart
+    for (i, c) in classes.iter().enumerate() {
+        let methods = c.methods.iter().map(|m| {
+            if let Some(done) = relocated.get(&Rc::as_ptr(m)) {
+                return Ok(done.clone());
+            }
+            let body = m.body.as_ref().map(|body| body.iter().map(|op| Ok(match op {
+                New(class) => New(class_id(*class)?),
+                Is(range) if *range != TypeRange::EMPTY => Is(to.map[&from.classes[range.0].name]),
+                Call(selector, argc, site) => {
+                    let name = from.selectors.name(*selector);
+                    Call(to.selectors.get(name).ok_or_else(|| AdvError::UnknownMethod { class: None, method: name.to_string() })?, *argc, *site)
+                },
+                op => op.to_owned(),
+            })).collect::<Result<Vec<_>>>()).transpose()?;
+            let done = Rc::new(CompiledMethod {
+                body,
+                caches: vec![InlineCache::default(); m.caches.len()],
+                spans: m.spans.to_owned(),
+                ..**m
+            });
+            relocated.insert(Rc::as_ptr(m), done.clone());
+            Ok(done)
+        }).collect::<Result<Vec<_>>>()?;
+        result[class_id(i)?] = Some(CompiledClass::new(to, c.fields.to_owned(), methods)?);
     }
 
     Ok(result)
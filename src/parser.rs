@@ -1,18 +1,19 @@
 use std::slice::Iter;
 use std::iter::Peekable;
-
-use anyhow::{Result, bail, ensure};
+use std::sync::Arc;
 
 use crate::syntax::*;
+use crate::error::*;
 use crate::lexer::*;
+use crate::namespace::*;
 
 macro_rules! pmatch {
     ($ctx:expr, $( $kind:ident => $kind_expr:expr ),* $(,)?) => {
         match $ctx.iter.next() {
             $( Some(Token { kind: TokenKind::$kind, .. }) => $kind_expr, )*
-            None => bail!("{}: Expected a token, found eof", $ctx.file_name),
+            None => bail!(AdvError::Parse { span: $ctx.span(), message: "Expected a token, found eof".to_string() }),
             Some(Token { line, column, kind }) => {
-                bail!("{}:{}:{}: Expected one of: {}; Got: {:?}", $ctx.file_name, line, column, stringify!($($kind),*), kind);
+                bail!(AdvError::Parse { span: $ctx.span_at(*line, *column), message: format!("Expected one of: {}; Got: {:?}", stringify!($($kind),*), kind) });
             },
         }
     };
@@ -20,9 +21,9 @@ macro_rules! pmatch {
         match $ctx.iter.next() {
             Some(Token { kind: TokenKind::Identifier($name, $is_str), .. }) => $ident_expr,
             $( Some(Token { kind: TokenKind::$kind, .. }) => $kind_expr, )*
-            None => bail!("{}: Expected a token, found eof", $ctx.file_name),
+            None => bail!(AdvError::Parse { span: $ctx.span(), message: "Expected a token, found eof".to_string() }),
             Some(Token { line, column, kind }) => {
-                bail!("{}:{}:{}: Expected one of: Indentifier, {}; Got: {:?}", $ctx.file_name, line, column, stringify!($($kind),*), kind);
+                bail!(AdvError::Parse { span: $ctx.span_at(*line, *column), message: format!("Expected one of: Indentifier, {}; Got: {:?}", stringify!($($kind),*), kind) });
             },
         }
     };
@@ -44,28 +45,19 @@ macro_rules! expect {
     }
 }
 
-macro_rules! str_identifier { // is it a good idea to allocate a new string each time?
-    ($name:expr, $is_str:expr) => {
-        if !$is_str {
-            $name.to_owned()
-        } else {
-            format!("'{}'", $name)
-        }
-    }
-}
-
 macro_rules! expect_identifier {
     ($ctx:expr) => {
         pmatch!($ctx,
-            Identifier(name, is_str) => str_identifier!(name, is_str),
+            Identifier(name, _is_str) => *name,
         )
     }
 }
 
+// The contents of a string, without the quotes
 macro_rules! expect_str {
     ($ctx:expr) => {
         pmatch!($ctx,
-            Identifier(name, true) => name,
+            Identifier(name, true) => name.as_str()[1..name.as_str().len() - 1].to_owned(),
         )
     }
 }
@@ -78,13 +70,38 @@ macro_rules! is {
 
 struct ParseCtx<'a> {
     pub iter: Peekable<Iter<'a, Token>>,
-    pub file_name: &'a str,
+    pub file: Arc<str>,
+    pub eof: (usize, usize), // Reported as the position of errors at the end of the file
+}
+
+impl<'a> ParseCtx<'a> {
+    fn new(file_name: &str, tokens: &'a [Token]) -> Self {
+        Self {
+            iter: tokens.iter().peekable(),
+            file: file_name.into(),
+            eof: tokens.last().map_or((1, 0), |t| (t.line, t.column)),
+        }
+    }
+
+    // Span of the next token
+    fn span(&mut self) -> Span {
+        let (line, column) = self.iter.peek().map_or(self.eof, |t| (t.line, t.column));
+        self.span_at(line, column)
+    }
+
+    fn span_at(&self, line: usize, column: usize) -> Span {
+        Span {
+            file: self.file.clone(),
+            line,
+            column,
+        }
+    }
 }
 
 fn parse_list<T>(ctx: &mut ParseCtx, parser: fn(&mut ParseCtx) -> Result<T>) -> Result<Vec<T>> {
     expect!(ctx, OpeningParens);
     let mut elements = Vec::new();
-    if ctx.iter.next_if(|t| t.kind == TokenKind::ClosingParens) == None {
+    if ctx.iter.next_if(|t| t.kind == TokenKind::ClosingParens).is_none() {
         loop {
             elements.push(parser(ctx)?);
             pmatch!(ctx,
@@ -98,8 +115,9 @@ fn parse_list<T>(ctx: &mut ParseCtx, parser: fn(&mut ParseCtx) -> Result<T>) ->
 }
 
 fn parse_expression(ctx: &mut ParseCtx) -> Result<Expression> {
+    let span = ctx.span();
     pmatch!(ctx,
-        Identifier(name, is_str) => parse_expression_further(ctx, Expression::Get(str_identifier!(name, is_str))),
+        Identifier(name, _is_str) => parse_expression_further(ctx, Expression { kind: ExpressionKind::Get(*name), span }),
         OpeningParens => {
             let result = parse_expression(ctx)?;
             expect!(ctx, ClosingParens);
@@ -112,52 +130,57 @@ fn parse_expression_further(ctx: &mut ParseCtx, expr: Expression) -> Result<Expr
     pmatch_maybe!(ctx.iter.peek(), 
         Some(TokenKind::Dot) => {
             ctx.iter.next();
+            let span = ctx.span();
             let name = expect_identifier!(ctx);
             if let Some(TokenKind::OpeningParens) = ctx.iter.peek().map(|t| &t.kind) {
                 let args = parse_list(ctx, parse_expression)?;
-                parse_expression_further(ctx, Expression::Call(Box::new(expr), name.to_owned(), args))
+                parse_expression_further(ctx, Expression { kind: ExpressionKind::Call(Box::new(expr), name, args), span })
             } else {
-                parse_expression_further(ctx, Expression::GetF(Box::new(expr), name.to_owned()))
+                parse_expression_further(ctx, Expression { kind: ExpressionKind::GetF(Box::new(expr), name), span })
             }
         },
         Some(TokenKind::Is) => {
+            let span = ctx.span();
             ctx.iter.next();
             let name = expect_identifier!(ctx);
-            parse_expression_further(ctx, Expression::Is(Box::new(expr), name.to_owned()))
+            parse_expression_further(ctx, Expression { kind: ExpressionKind::Is(Box::new(expr), name), span })
         },
         Some(TokenKind::EqualsSign) => {
+            let span = ctx.span();
             ctx.iter.next();
-            Ok(Expression::Equals(Box::new(expr), Box::new(parse_expression(ctx)?)))
+            Ok(Expression { kind: ExpressionKind::Equals(Box::new(expr), Box::new(parse_expression(ctx)?)), span })
         },
         _ => Ok(expr)
     )
 }
 
 fn parse_statement(ctx: &mut ParseCtx) -> Result<Statement> {
+    let span = ctx.span();
     Ok(pmatch_maybe!(ctx.iter.peek(),
         Some(TokenKind::Return) => {
             ctx.iter.next();
-            Statement::Return(parse_expression(ctx)?)
+            Statement { kind: StatementKind::Return(parse_expression(ctx)?), span }
         },
         Some(TokenKind::If) => {
             ctx.iter.next();
-            Statement::If(parse_expression(ctx)?, parse_block(ctx)?)
+            Statement { kind: StatementKind::If(parse_expression(ctx)?, parse_block(ctx)?), span }
         },
         Some(TokenKind::While) => {
             _ = ctx.iter.next();
-            Statement::While(parse_expression(ctx)?, parse_block(ctx)?)
+            Statement { kind: StatementKind::While(parse_expression(ctx)?, parse_block(ctx)?), span }
         },
         _ => {
             let expr = parse_expression(ctx)?;
-            match expr {
-                Expression::Equals(a, b) => match *a {
-                    Expression::Get(var) => Statement::SetV(var, *b),
-                    Expression::GetF(obj, field) => Statement::SetF(*obj, field, *b),
-                    _ => bail!("You can't just set a random expression lol"),
+            let kind = match expr.kind {
+                ExpressionKind::Equals(a, b) => match a.kind {
+                    ExpressionKind::Get(var) => StatementKind::SetV(var, *b),
+                    ExpressionKind::GetF(obj, field) => StatementKind::SetF(*obj, field, *b),
+                    _ => bail!(AdvError::Parse { span: expr.span, message: "You can't just set a random expression lol".to_string() }),
                 },
-                Expression::Call(obj, method, args) => Statement::Call(*obj, method, args),
-                _ => bail!("Expected a statement, got an expression instead"),
-            }
+                ExpressionKind::Call(obj, method, args) => StatementKind::Call(*obj, method, args),
+                _ => bail!(AdvError::Parse { span: expr.span, message: "Expected a statement, got an expression instead".to_string() }),
+            };
+            Statement { kind, span: expr.span }
         }
     ))
 }
@@ -166,7 +189,7 @@ fn parse_block(ctx: &mut ParseCtx) -> Result<Vec<Statement>> {
     let mut result = Vec::new();
     
     expect!(ctx, BlockStart);
-    while ctx.iter.next_if(|t| t.kind == TokenKind::BlockEnd) == None {
+    while ctx.iter.next_if(|t| t.kind == TokenKind::BlockEnd).is_none() {
         result.push(parse_statement(ctx)?);
     }
 
@@ -174,31 +197,44 @@ fn parse_block(ctx: &mut ParseCtx) -> Result<Vec<Statement>> {
 }
 
 fn parse_class(ctx: &mut ParseCtx) -> Result<Class> {
+    let private = ctx.iter.next_if(|t| t.kind == TokenKind::Private).is_some();
     expect!(ctx, Class);
+    let span = ctx.span();
     let name = expect_identifier!(ctx);
     expect!(ctx, Extends);
     let parent = expect_identifier!(ctx);
     expect!(ctx, BlockStart);
     
     let mut fields = Vec::new();
+    let mut field_spans = Vec::new();
+    let mut private_fields = Vec::new();
     let mut methods = Vec::new();
 
     loop {
+        let private = ctx.iter.next_if(|t| t.kind == TokenKind::Private).is_some();
+        ensure!(!private || !is!(ctx.iter.peek(), BlockEnd), AdvError::Parse { span: ctx.span(), message: "Expected a field or a method after 'private'".to_string() });
         pmatch!(ctx,
             Field => {
+                field_spans.push(ctx.span());
                 let name = expect_identifier!(ctx);
-                fields.push(name.to_owned());
+                if private {
+                    private_fields.push(name);
+                }
+                fields.push(name);
             },
             Method => {
+                let span = ctx.span();
                 let name = expect_identifier!(ctx);
                 methods.push(Method {
-                    name: name.to_owned(),
-                    params: parse_list(ctx, |ctx| Ok(expect_identifier!(ctx).to_owned()))?,
+                    name,
+                    params: parse_list(ctx, |ctx| Ok(expect_identifier!(ctx)))?,
                     body: if is!(ctx.iter.peek(), BlockStart) {
                         Some(parse_block(ctx)?)
                     } else {
                         None
                     },
+                    private,
+                    span,
                 });
             },
             BlockEnd => break,
@@ -206,10 +242,15 @@ fn parse_class(ctx: &mut ParseCtx) -> Result<Class> {
     }
 
     Ok(Class {
-        name: name.to_owned(),
-        parent: Some(parent.to_owned()),
+        name,
+        module: module_of(name),
+        parent: Some(parent),
         own_fields: fields,
+        field_spans,
+        private_fields,
         own_methods: methods,
+        private,
+        span,
     })
 }
 
@@ -219,13 +260,17 @@ fn parse_metadata(ctx: &mut ParseCtx) -> Result<Metadata> {
     loop {
         pmatch_maybe!(ctx.iter.peek(),
             Some(TokenKind::Identifier(name, false)) => {
+                let span = ctx.span();
                 ctx.iter.next();
                 expect!(ctx, BlockStart);
                 match name.as_str() {
-                    "target" => result.target = expect_str!(ctx).to_owned(),
-                    "import" => result.dependencies.push(expect_str!(ctx).to_owned()),
-                    "entrypoint" => result.entrypoints.push(expect_str!(ctx).to_owned()),
-                    x => bail!("'{x}' is not a valid metadata entry"),
+                    "target" => result.target = expect_str!(ctx),
+                    "import" => result.dependencies.push(expect_str!(ctx)),
+                    "module" => result.module = Some(expect_str!(ctx)),
+                    "use" => result.uses.push(expect_str!(ctx)),
+                    "entrypoint" => result.entrypoints.push(expect_str!(ctx)),
+                    "singleton" => result.singletons.push(expect_str!(ctx)),
+                    x => bail!(AdvError::Parse { span, message: format!("'{x}' is not a valid metadata entry") }),
                 }
             },
             _ => return Ok(result)
@@ -234,18 +279,39 @@ fn parse_metadata(ctx: &mut ParseCtx) -> Result<Metadata> {
 }
 
 pub fn parse(file_name: &str, tokens: Vec<Token>) -> Result<(Metadata, Vec<Class>)> {
-    let mut ctx = ParseCtx {
-        iter: tokens.iter().peekable(),
-        file_name
-    };
+    let mut ctx = ParseCtx::new(file_name, &tokens);
 
     let metadata = parse_metadata(&mut ctx)?;
-    ensure!(metadata.target == CURRENT_VERSION, "Incompatible version! (program targets '{}', running '{}')", metadata.target, CURRENT_VERSION);
+    ensure!(metadata.target == CURRENT_VERSION, AdvError::VersionMismatch { target: metadata.target });
     let mut classes = Vec::new();
 
-    while ctx.iter.peek() != None {
+    while ctx.iter.peek().is_some() {
         classes.push(parse_class(&mut ctx)?);
     }
 
     Ok((metadata, classes))
 }
+
+pub fn parse_classes(file_name: &str, tokens: Vec<Token>) -> Result<Vec<Class>> {
+    let mut ctx = ParseCtx::new(file_name, &tokens);
+
+    let mut classes = Vec::new();
+
+    while ctx.iter.peek().is_some() {
+        classes.push(parse_class(&mut ctx)?);
+    }
+
+    Ok(classes)
+}
+
+pub fn parse_statements(file_name: &str, tokens: Vec<Token>) -> Result<Vec<Statement>> {
+    let mut ctx = ParseCtx::new(file_name, &tokens);
+
+    let mut statements = Vec::new();
+
+    while ctx.iter.peek().is_some() {
+        statements.push(parse_statement(&mut ctx)?);
+    }
+
+    Ok(statements)
+}
@@ -0,0 +1,198 @@
+use std::io::{self, prelude::*};
+use std::path;
+
+use anyhow::{Result, Context};
+
+use advrs::lexer::*;
+use advrs::symbol::*;
+use advrs::syntax::*;
+use advrs::parser::*;
+use advrs::loader::*;
+use advrs::class_table::*;
+use advrs::opcode::*;
+use advrs::interpreter::*;
+use advrs::builtins::*;
+use advrs::gc::*;
+use advrs::error::Warning;
+
+use crate::choose_entrypoint;
+
+const FILE_NAME: &str = "<repl>";
+
+struct Session {
+    classes: Vec<Class>,
+    ctx: RunCtx,
+    gc: GC,
+    stack: VmStack, // stack[0] is the entrypoint, followed by the values of `locals`
+    locals: Vec<Symbol>,
+    singletons: Vec<String>,
+}
+
+impl Session {
+    fn new(path: Option<&path::Path>, lib_path: &[path::PathBuf], stack_size: usize, gc_config: GcConfig) -> Result<Self> {
+        let (metadata, classes) = if let Some(path) = path {
+            load(path, lib_path)?
+        } else {
+            (Metadata::default(), vec![])
+        };
+        let classes = [builtin_classes(), classes].concat();
+
+        let table = ClassTable::create(&classes)?;
+        let compiled = compile_and_warn(&table)?;
+        let entrypoint = if metadata.entrypoints.is_empty() {
+            table.get_class_id("Object".into())?
+        } else {
+            choose_entrypoint(&metadata, &table)?
+        };
+
+        let mut stack = VmStack::new(stack_size);
+        let mut gc = GC::new(gc_config);
+        let mut builtins = Builtins::default();
+        builtins.register_chars(table.classes[entrypoint].name, Stdio::default());
+        let ctx = RunCtx::new(&mut gc, table, compiled, &metadata.singletons, entrypoint, builtins)?;
+        stack.push(ctx.entrypoint)?;
+
+        Ok(Self {
+            classes,
+            ctx,
+            gc,
+            stack,
+            locals: vec![],
+            singletons: metadata.singletons,
+        })
+    }
+
+    fn eval(&mut self, tokens: Vec<Token>) -> Result<()> {
+        match tokens.first() {
+            None => (),
+            Some(Token { kind: TokenKind::Class | TokenKind::Private, .. }) => {
+                let classes = parse_classes(FILE_NAME, tokens)?;
+                let names = classes.iter().map(|c| c.name.to_string()).collect::<Vec<_>>();
+                self.define_classes(classes)?;
+                println!("Defined {}", names.join(", "));
+            },
+            Some(_) => {
+                let statements = parse_statements(FILE_NAME, tokens)?;
+                if let Some(result) = self.run_statements(statements)? {
+                    println!("=> {}", result.class_name(&self.ctx.class_table));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn run_statements(&mut self, statements: Vec<Statement>) -> Result<Option<Object>> {
+        let returns = matches!(statements.last(), Some(Statement { kind: StatementKind::Return(_), .. }));
+        // Calling a method that no class defines yet is only an error once it runs
+        self.ctx.class_table.selectors.intern_calls(&statements);
+        let method = Method {
+            name: "<repl>".into(), // Can't be produced by the lexer, so the body can never be turned into a `Recurse`
+            params: vec![],
+            body: Some(statements),
+            private: false,
+            span: Span::new(FILE_NAME, 0, 0),
+        };
+
+        let mut locals = self.locals.to_owned();
+        // The statements run as if they were a method of the entrypoint
+        let this_fields = &self.ctx.classes[self.ctx.entrypoint.class()].fields;
+        let mut warnings = Vec::new();
+        let compiled = compile_method_with_locals(&self.ctx.class_table, self.ctx.entrypoint.class(), &method, this_fields, &mut locals, &mut warnings);
+        print_warnings(&warnings);
+        let compiled = compiled?;
+        self.locals = locals;
+
+        let result = run(&self.ctx, &mut self.gc, &mut self.stack, &compiled);
+        // An error might've left some values on the stack
+        self.stack.truncate(1 + self.locals.len());
+        self.stack.resize(1 + self.locals.len())?;
+        io::stdout().flush()?;
+
+        let result = result.with_context(|| "Runtime error")?;
+        Ok(if returns { Some(result) } else { None })
+    }
+
+    fn define_classes(&mut self, new_classes: Vec<Class>) -> Result<()> {
+        let classes = [self.classes.to_owned(), new_classes].concat();
+        let mut table = ClassTable::create(&classes)?;
+        // Selectors interned by earlier statements are kept, `relocate` needs every one of them
+        for name in self.ctx.class_table.selectors.names() {
+            table.selectors.intern(*name);
+        }
+        // Only the new classes get compiled, the code of the existing ones is moved over to the new ids
+        let done = relocate(&self.ctx.class_table, &table, &self.ctx.classes)?;
+        let mut warnings = Vec::new();
+        let compiled = compile_missing(&table, done, &mut warnings);
+        print_warnings(&warnings);
+        let compiled = compiled?;
+        let singletons = find_singletons(&table, &compiled, &self.singletons)?;
+        self.ctx.builtins.check(&table, &compiled)?;
+
+        // Adding classes can shift the ids of existing ones, so every live object has to be updated
+        let id_map = self.ctx.class_table.classes.iter().map(|c| table.get_class_id(c.name)).collect::<Result<Vec<_>, _>>()?;
+        self.gc.remap_classes(self.stack.slice_mut(0..1 + self.locals.len())?, &id_map);
+
+        self.ctx = RunCtx {
+            singletons,
+            class_table: table,
+            classes: compiled,
+            entrypoint: self.stack.get(0)?,
+            builtins: std::mem::take(&mut self.ctx.builtins),
+            cache_stats: std::mem::take(&mut self.ctx.cache_stats),
+        };
+        self.classes = classes;
+
+        Ok(())
+    }
+}
+
+fn print_warnings(warnings: &[Warning]) {
+    for w in warnings {
+        eprintln!("{w}");
+    }
+}
+
+fn compile_and_warn(table: &ClassTable) -> Result<Vec<CompiledClass>> {
+    let mut warnings = Vec::new();
+    let compiled = compile(table, &mut warnings);
+    print_warnings(&warnings);
+    Ok(compiled?)
+}
+
+pub fn repl(path: Option<&path::Path>, lib_path: &[path::PathBuf], stack_size: usize, gc_config: GcConfig) -> Result<()> {
+    let mut session = Session::new(path, lib_path, stack_size, gc_config)?;
+    let mut buffer = String::new();
+
+    loop {
+        print!("{}", if buffer.is_empty() { "> " } else { "... " });
+        io::stdout().flush()?;
+
+        if io::stdin().read_line(&mut buffer)? == 0 {
+            println!();
+            return Ok(());
+        }
+
+        let tokens = match tokenize(FILE_NAME, &buffer) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                eprintln!("Error: {e:#}");
+                buffer.clear();
+                continue;
+            },
+        };
+
+        let depth = tokens.iter().fold(0isize, |depth, t| match t.kind {
+            TokenKind::BlockStart => depth + 1,
+            TokenKind::BlockEnd => depth - 1,
+            _ => depth,
+        });
+        if depth > 0 {
+            continue;
+        }
+        buffer.clear();
+
+        if let Err(e) = session.eval(tokens) {
+            eprintln!("Error: {e:#}");
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::syntax::*;
-use crate::syntax::Expression::*;
+use crate::syntax::ExpressionKind::*;
 
 struct CodeBuilder {
     code: String,
@@ -14,24 +14,17 @@ impl CodeBuilder {
         }
     }
 
-    pub fn with_capacity(cap: usize) -> Self {
-        Self {
-            code: String::with_capacity(cap),
-            tab_index: 0
-        }
-    }
-
     pub fn line<S: AsRef<str>>(&mut self, s: S) -> &mut Self {
         for _ in 0..self.tab_index {
             self.code.push_str("    ");
         }
         self.code.push_str(s.as_ref());
-        self.code.push_str("\n");
+        self.code.push('\n');
         self
     }
 
     pub fn newline(&mut self) -> &mut Self {
-        self.code.push_str("\n");
+        self.code.push('\n');
         self
     }
 
@@ -48,46 +41,42 @@ impl CodeBuilder {
     pub fn into_string(self) -> String {
         self.code
     }
-
-    pub fn as_str(&self) -> &str {
-        &self.code
-    }
 }
 
-fn stringify_list<T>(list: &Vec<T>, stringifier: fn(&T) -> String) -> String {
+fn stringify_list<T>(list: &[T], stringifier: fn(&T) -> String) -> String {
     format!("({})", list.iter().map(stringifier).collect::<Vec<String>>().join(", "))
 }
 
 fn stringify_expression(expr: &Expression) -> String {
-    match expr {
-        Get(name) => name.to_owned(),
-        GetF(obj, name) => stringify_expression(obj) + "." + name,
-        Call(obj, name, args) => stringify_expression(obj) + "." + name + stringify_list(args, stringify_expression).as_str(),
+    match &expr.kind {
+        Get(name) => name.to_string(),
+        GetF(obj, name) => stringify_expression(obj) + "." + name.as_str(),
+        Call(obj, name, args) => stringify_expression(obj) + "." + name.as_str() + stringify_list(args, stringify_expression).as_str(),
         Is(obj, class) => format!("{} is {}", stringify_expression(obj), class),
         Equals(obj1, obj2) => format!("{} = {}", stringify_expression(obj1), stringify_expression(obj2)),
     }
 }
 
 fn stringify_statement(bd: &mut CodeBuilder, stmt: &Statement) {
-    match stmt {
-        Statement::Return(expr) => {
+    match &stmt.kind {
+        StatementKind::Return(expr) => {
             bd.line(format!("return {}", stringify_expression(expr)));
         },
-        Statement::If(cond, block) => {
+        StatementKind::If(cond, block) => {
             bd.line(format!("if {}:", stringify_expression(cond)));
             stringify_block(bd, block);
         },
-        Statement::While(cond, block) => {
+        StatementKind::While(cond, block) => {
             bd.line(format!("while {}:", stringify_expression(cond)));
             stringify_block(bd, block);
         },
-        Statement::SetV(var, val) => {
+        StatementKind::SetV(var, val) => {
             bd.line(format!("{} = {}", var, stringify_expression(val)));
         },
-        Statement::SetF(obj, field, val) => {
+        StatementKind::SetF(obj, field, val) => {
             bd.line(format!("{}.{} = {}", stringify_expression(obj), field, stringify_expression(val)));
         },
-        Statement::Call(obj, method, args) => {
+        StatementKind::Call(obj, method, args) => {
             bd.line(format!("{}.{}{}", stringify_expression(obj), method, stringify_list(args, stringify_expression)));
         }
     }
@@ -103,24 +92,28 @@ fn stringify_block(bd: &mut CodeBuilder, stmts: &[Statement]) {
     bd.untab().line("end");
 }
 
+fn private(private: bool) -> &'static str {
+    if private { "private " } else { "" }
+}
+
 fn stringify_class(bd: &mut CodeBuilder, class: &Class) {
     if let Some(p) = &class.parent {
-        bd.line(format!("class {} extends {}:", class.name, p));
+        bd.line(format!("{}class {} extends {}:", private(class.private), class.name, p));
     } else {
-        bd.line(format!("class {}:", class.name));
+        bd.line(format!("{}class {}:", private(class.private), class.name));
     }
 
     bd.tab();
 
     for f in &class.own_fields {
-        bd.line(format!("field {}", f));
+        bd.line(format!("{}field {}", private(class.private_fields.contains(f)), f));
     }
 
     for m in &class.own_methods {
-        bd.line(format!("method {}{}{}", m.name, stringify_list(&m.params, |s| s.to_string()), if m.body.is_some() { ":" } else { "" }));
+        bd.line(format!("{}method {}{}{}", private(m.private), m.name, stringify_list(&m.params, |s| s.to_string()), if m.body.is_some() { ":" } else { "" }));
 
         if let Some(b) = &m.body {
-            stringify_block(bd, &b);
+            stringify_block(bd, b);
         }
     }
 
@@ -134,10 +127,25 @@ fn stringify_metadata(bd: &mut CodeBuilder, metadata: &Metadata) {
         bd.line(format!("import: '{}'", import));
     }
     bd.newline();
+    if metadata.module.is_some() || !metadata.uses.is_empty() {
+        if let Some(module) = &metadata.module {
+            bd.line(format!("module: '{}'", module));
+        }
+        for entry in &metadata.uses {
+            bd.line(format!("use: '{}'", entry));
+        }
+        bd.newline();
+    }
     for entry in &metadata.entrypoints {
         bd.line(format!("entrypoint: '{}'", entry));
     }
     bd.newline();
+    if !metadata.singletons.is_empty() {
+        for singleton in &metadata.singletons {
+            bd.line(format!("singleton: '{}'", singleton));
+        }
+        bd.newline();
+    }
 }
 
 pub fn stringify(metadata: &Metadata, classes: &[Class]) -> String {
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{LazyLock, Mutex};
+
+// An interned identifier, comparing and hashing them is as cheap as it is for integers
+// The strings live for the rest of the program, so turning a symbol back into text never has to allocate
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct Symbol(u32);
+
+struct Interner {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, Symbol>,
+}
+
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(|| Mutex::new(Interner {
+    names: Vec::new(),
+    ids: HashMap::new(),
+}));
+
+impl Symbol {
+    pub fn intern(name: &str) -> Self {
+        let mut interner = INTERNER.lock().unwrap();
+        if let Some(symbol) = interner.ids.get(name) {
+            return *symbol;
+        }
+
+        let name: &'static str = Box::leak(name.into());
+        let symbol = Symbol(interner.names.len().try_into().expect("Too many symbols :<"));
+        interner.names.push(name);
+        interner.ids.insert(name, symbol);
+        symbol
+    }
+
+    // Like `intern`, but doesn't create a new symbol if there isn't one already
+    pub fn lookup(name: &str) -> Option<Self> {
+        INTERNER.lock().unwrap().ids.get(name).copied()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.lock().unwrap().names[self.0 as usize]
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Self::intern(name)
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
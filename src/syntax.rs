@@ -1,19 +1,64 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::symbol::*;
+
 pub const CURRENT_VERSION: &str = "indev";
 
+#[derive(PartialEq, Eq, Clone, Debug, Hash)]
+pub struct Span {
+    pub file: Arc<str>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(file: &str, line: usize, column: usize) -> Self {
+        Self {
+            file: file.into(),
+            line,
+            column,
+        }
+    }
+
+    pub fn builtin() -> Self {
+        Self::new("<builtin>", 0, 0)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+// Spans point at the token that defines the node, e.g. the method name of a call or the '=' of an assignment
+#[derive(PartialEq, Clone, Debug)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
 #[derive(PartialEq, Clone, Debug)]
-pub enum Expression {
-    Get(String),
-    GetF(Box<Expression>, String),
-    Call(Box<Expression>, String, Vec<Expression>),
-    Is(Box<Expression>, String),
+pub enum ExpressionKind {
+    Get(Symbol),
+    GetF(Box<Expression>, Symbol),
+    Call(Box<Expression>, Symbol, Vec<Expression>),
+    Is(Box<Expression>, Symbol),
     Equals(Box<Expression>, Box<Expression>),
 }
 
 #[derive(PartialEq, Clone, Debug)]
-pub enum Statement {
-    SetV(String, Expression),
-    SetF(Expression, String, Expression),
-    Call(Expression, String, Vec<Expression>),
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum StatementKind {
+    SetV(Symbol, Expression),
+    SetF(Expression, Symbol, Expression),
+    Call(Expression, Symbol, Vec<Expression>),
     Return(Expression),
     If(Expression, Vec<Statement>),
     While(Expression, Vec<Statement>),
@@ -21,32 +66,45 @@ pub enum Statement {
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct Method {
-    pub name: String,
-    pub params: Vec<String>,
-    pub body: Option<Vec<Statement>>
+    pub name: Symbol,
+    pub params: Vec<Symbol>,
+    pub body: Option<Vec<Statement>>,
+    pub private: bool,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct Class {
-    pub name: String,
-    pub parent: Option<String>,
-    pub own_fields: Vec<String>,
+    pub name: Symbol, // Qualified with the module, if there is one
+    pub module: Option<Symbol>,
+    pub parent: Option<Symbol>,
+    pub own_fields: Vec<Symbol>,
+    pub field_spans: Vec<Span>, // Parallel to `own_fields`
+    pub private_fields: Vec<Symbol>, // The subset of `own_fields` declared with `private`
     pub own_methods: Vec<Method>,
+    pub private: bool,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct Metadata {
     pub target: String,
     pub dependencies: Vec<String>,
+    pub module: Option<String>,
+    pub uses: Vec<String>,
     pub entrypoints: Vec<String>,
+    pub singletons: Vec<String>,
 }
 
-impl Metadata {
-    pub fn default() -> Self {
+impl Default for Metadata {
+    fn default() -> Self {
         Self {
             target: CURRENT_VERSION.to_string(),
             dependencies: vec![],
+            module: None,
+            uses: vec![],
             entrypoints: vec![],
+            singletons: vec![],
         }
     }
 }
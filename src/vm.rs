@@ -0,0 +1,216 @@
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use crate::symbol::*;
+use crate::error::*;
+use crate::loader::*;
+use crate::class_table::*;
+use crate::opcode::*;
+use crate::interpreter::*;
+use crate::builtins::*;
+use crate::gc::*;
+
+pub struct VmBuilder {
+    files: Vec<PathBuf>,
+    sources: Vec<(String, String)>,
+    search_path: Vec<PathBuf>,
+    entrypoint: Option<String>,
+    stack_size: usize,
+    gc: GcConfig,
+    builtins: Builtins,
+    stdio: Stdio,
+}
+
+impl VmBuilder {
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.files.push(path.into());
+        self
+    }
+
+    // `name` is used in error messages and imports are resolved relative to it, like for a file with that path
+    pub fn source(mut self, name: &str, source: &str) -> Self {
+        self.sources.push((name.to_owned(), source.to_owned()));
+        self
+    }
+
+    // Where imports are looked up when they aren't next to the importing file, see `loader::search_path`
+    pub fn search_path(mut self, dirs: &[PathBuf]) -> Self {
+        self.search_path = dirs.to_owned();
+        self
+    }
+
+    // Only needed when the program doesn't have exactly one entrypoint, otherwise `Object` is used
+    pub fn entrypoint(mut self, class: &str) -> Self {
+        self.entrypoint = Some(class.to_owned());
+        self
+    }
+
+    pub fn stack_size(mut self, slots: usize) -> Self {
+        self.stack_size = slots;
+        self
+    }
+
+    pub fn gc(mut self, config: GcConfig) -> Self {
+        self.gc = config;
+        self
+    }
+
+    pub fn builtin(mut self, class: &str, method: &str, arity: usize, builtin: impl Fn(&RunCtx, &mut GC, Object, &[Object]) -> Result<Object, RuntimeErrorKind> + 'static) -> Self {
+        self.builtins.register(class.into(), method.into(), arity, builtin);
+        self
+    }
+
+    pub fn builtins(mut self, builtins: Builtins) -> Self {
+        self.builtins.merge(builtins);
+        self
+    }
+
+    // Used by the char builtins of the entrypoint, instead of the stdin and stdout of the process
+    pub fn stdin(mut self, input: impl BufRead + 'static) -> Self {
+        self.stdio.input = Some(Box::new(input));
+        self
+    }
+
+    pub fn stdout(mut self, output: impl Write + 'static) -> Self {
+        self.stdio.output = Some(Box::new(output));
+        self
+    }
+
+    pub fn build(self) -> Result<Vm> {
+        let (metadata, classes) = load_roots(&self.files, &self.sources, &self.search_path)?;
+        let table = ClassTable::create(&[builtin_classes(), classes].concat())?;
+        let mut warnings = Vec::new();
+        let compiled = compile(&table, &mut warnings)?;
+
+        let singletons = metadata.iter().flat_map(|m| m.singletons.to_owned()).collect::<Vec<_>>();
+        let entrypoints = metadata.iter().flat_map(|m| m.entrypoints.to_owned()).collect::<Vec<_>>();
+        let entrypoint = match (&self.entrypoint, &entrypoints[..]) {
+            (Some(name), _) | (None, [name]) => table.lookup_class_id(name)?,
+            _ => table.get_class_id("Object".into())?,
+        };
+        let mut vm = self.build_compiled(table, compiled, &singletons, entrypoint)?;
+        vm.warnings = warnings;
+        Ok(vm)
+    }
+
+    // For programs that were compiled separately, like the ones loaded from bytecode, the files and sources of the builder are ignored
+    pub fn build_compiled(self, table: ClassTable, compiled: Vec<CompiledClass>, singletons: &[String], entrypoint: usize) -> Result<Vm> {
+        self.gc.validate()?;
+        let mut builtins = Builtins::default();
+        builtins.register_chars(table.classes[entrypoint].name, self.stdio);
+        builtins.merge(self.builtins);
+
+        let stack = VmStack::new(self.stack_size);
+        let mut gc = GC::new(self.gc);
+        let ctx = RunCtx::new(&mut gc, table, compiled, singletons, entrypoint, builtins)?;
+        Ok(Vm {
+            ctx,
+            gc,
+            stack,
+            warnings: vec![],
+        })
+    }
+}
+
+// A loaded program, along with its heap and stack
+// Objects are handed out as handles, which keep them alive until they're dropped
+pub struct Vm {
+    ctx: RunCtx,
+    gc: GC,
+    stack: VmStack,
+    warnings: Vec<Warning>,
+}
+
+impl Vm {
+    pub fn builder() -> VmBuilder {
+        VmBuilder {
+            files: vec![],
+            sources: vec![],
+            search_path: vec![],
+            entrypoint: None,
+            stack_size: VmStack::DEFAULT_MAX_SIZE,
+            gc: GcConfig::default(),
+            builtins: Builtins::default(),
+            stdio: Stdio::default(),
+        }
+    }
+
+    pub fn context(&self) -> &RunCtx {
+        &self.ctx
+    }
+
+    // Found while compiling the files and sources of the builder
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    // Panics if the handle was created by another vm, since its object would be meaningless here
+    fn object(&self, handle: &Handle) -> Object {
+        assert!(handle.belongs_to(&self.gc), "The handle belongs to a different vm");
+        handle.object()
+    }
+
+    // The instance of the entrypoint class that gets created along with the vm
+    pub fn entrypoint(&mut self) -> Handle {
+        self.gc.handle(self.ctx.entrypoint)
+    }
+
+    // Names given by the host are only looked up, interning them would keep every misspelled one around forever
+    fn class_id(&self, name: &str) -> Result<usize> {
+        self.ctx.class_table.lookup_class_id(name)
+    }
+
+    pub fn instantiate(&mut self, class: &str) -> Result<Handle> {
+        let class = self.class_id(class)?;
+        if self.gc.should_collect() {
+            self.collect();
+        }
+        let obj = Object::new(&self.ctx, &mut self.gc, class)?;
+        Ok(self.gc.handle(obj))
+    }
+
+    pub fn call(&mut self, this: &Handle, method: &str, args: &[Handle]) -> Result<Handle> {
+        let this = self.object(this);
+        let class_name = self.ctx.class_table.classes[this.class()].name;
+        let method = Symbol::lookup(method).and_then(|m| self.ctx.class_table.selectors.get(m)).and_then(|s| self.ctx.classes[this.class()].lookup(s))
+            .ok_or_else(|| AdvError::UnknownMethod { class: Some(class_name), method: method.to_owned() })?;
+        ensure!(args.len() == method.params_count, RuntimeErrorKind::ArgumentCount { class: class_name, method: method.name, expected: method.params_count, provided: args.len() });
+
+        self.stack.push(this)?;
+        for a in args {
+            let a = self.object(a);
+            self.stack.push(a)?;
+        }
+        let result = run(&self.ctx, &mut self.gc, &mut self.stack, method);
+        self.stack.truncate(0);
+        Ok(self.gc.handle(result?))
+    }
+
+    pub fn class_name(&self, handle: &Handle) -> &'static str {
+        self.object(handle).class_name(&self.ctx.class_table)
+    }
+
+    fn field_index(&self, obj: Object, name: &str) -> Result<usize> {
+        let index = Symbol::lookup(name).and_then(|field| self.ctx.classes[obj.class()].fields.iter().position(|f| *f == field));
+        Ok(index.ok_or_else(|| RuntimeErrorKind::UndefinedField { class: self.ctx.class_table.classes[obj.class()].name, field: name.to_owned() })?)
+    }
+
+    pub fn field(&mut self, handle: &Handle, name: &str) -> Result<Handle> {
+        let obj = self.object(handle);
+        let index = self.field_index(obj, name)?;
+        let value = self.gc.get(obj, index)?;
+        Ok(self.gc.handle(value))
+    }
+
+    pub fn set_field(&mut self, handle: &Handle, name: &str, value: &Handle) -> Result<()> {
+        let obj = self.object(handle);
+        let index = self.field_index(obj, name)?;
+        let value = self.object(value);
+        Ok(self.gc.set(obj, index, value)?)
+    }
+
+    // Only objects that have a handle survive, since the stack is empty in between calls
+    pub fn collect(&mut self) -> GcStats {
+        self.gc.collect(self.stack.live())
+    }
+}
@@ -0,0 +1,117 @@
+use std::cell::Cell;
+use std::fs;
+use std::process::Command;
+use std::rc::Rc;
+
+use advrs::error::*;
+use advrs::lexer::*;
+use advrs::parser::*;
+use advrs::class_table::*;
+use advrs::opcode::*;
+use advrs::interpreter::*;
+use advrs::builtins::*;
+use advrs::gc::*;
+
+#[test]
+fn registered_builtins_are_inherited() {
+    let (_, classes) = parse("test.adv", tokenize("test.adv", "target: 'indev'
+class Host extends Object:
+    method tick(n)
+    method missing()
+end
+class Counter extends Host:
+    field last
+    method main():
+        this.last = this.tick(True)
+        this.tick(this.last)
+        return this.last
+    end
+end
+class True extends Object:
+end
+").unwrap()).unwrap();
+    let table = ClassTable::create(&[builtin_classes(), classes].concat()).unwrap();
+    let compiled = compile(&table, &mut vec![]).unwrap();
+    let entrypoint = table.get_class_id("Counter".into()).unwrap();
+
+    let ticks = Rc::new(Cell::new(0));
+    let mut builtins = Builtins::default();
+    let counter = ticks.clone();
+    builtins.register("Host".into(), "tick".into(), 1, move |ctx, _, this, args| {
+        counter.set(counter.get() + 1);
+        assert_eq!(this.class_name(&ctx.class_table), "Counter");
+        Ok(args[0])
+    });
+
+    let mut stack = VmStack::new(1024);
+    let mut gc = GC::new(GcConfig::default());
+    let ctx = RunCtx::new(&mut gc, table, compiled, &[], entrypoint, builtins).unwrap();
+    stack.push(ctx.entrypoint).unwrap();
+    let main = ctx.classes[entrypoint].methods.iter().find(|m| m.name == "main").unwrap();
+
+    let result = run(&ctx, &mut gc, &mut stack, main).unwrap();
+    assert_eq!(result.class_name(&ctx.class_table), "True");
+    assert_eq!(ticks.get(), 2);
+
+    let missing = ctx.classes[entrypoint].methods.iter().find(|m| m.name == "missing").unwrap();
+    stack.truncate(1);
+    let Err(AdvError::Runtime(error)) = run(&ctx, &mut gc, &mut stack, missing) else {
+        panic!("Expected a runtime error");
+    };
+    assert!(matches!(error.kind, RuntimeErrorKind::MissingBody { method } if method == "missing"), "{error:?}");
+}
+
+#[test]
+fn char_builtins_reject_other_classes() {
+    let source = "target: 'indev'
+entrypoint: 'Main'
+class Main extends Object:
+    method 'builtin:push_char'(c)
+    method 'builtin:pop_char'()
+    method 'builtin:write'()
+
+    method main():
+        this.'builtin:push_char'('\\0')
+        this.'builtin:push_char'(this.'builtin:pop_char'())
+        this.'builtin:write'()
+        this.'builtin:push_char'(this)
+        return this
+    end
+end
+class '\\0' extends Object:
+end
+";
+    let path = std::env::temp_dir().join(format!("advrs-chars-{}.adv", std::process::id()));
+    fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_advrs")).arg("run").arg(&path).output().unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, b"\0");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Expected a character class, got an instance of 'Main'"), "{stderr}");
+}
+
+#[test]
+fn builtins_have_to_match_the_declared_parameters() {
+    let (_, classes) = parse("test.adv", tokenize("test.adv", "target: 'indev'
+class Main extends Object:
+    method 'builtin:push_char'()
+    method 'builtin:write'()
+end
+").unwrap()).unwrap();
+    let table = ClassTable::create(&[builtin_classes(), classes].concat()).unwrap();
+    let compiled = compile(&table, &mut vec![]).unwrap();
+    let entrypoint = table.get_class_id("Main".into()).unwrap();
+    let ctx = |builtins| RunCtx::new(&mut GC::new(GcConfig::default()), table.clone(), compiled.clone(), &[], entrypoint, builtins);
+
+    let mut builtins = Builtins::default();
+    builtins.register_chars("Main".into(), Stdio::default());
+    let Err(error) = ctx(builtins) else {
+        panic!("Expected an arity error");
+    };
+    assert!(matches!(&error, AdvError::BuiltinArity { class, method, declared: 0, expected: 1 } if *class == "Main" && *method == "'builtin:push_char'"), "{error:?}");
+
+    let mut builtins = Builtins::default();
+    builtins.register("Main".into(), "'builtin:write'".into(), 2, |_, _, this, _| Ok(this));
+    assert!(matches!(ctx(builtins), Err(AdvError::BuiltinArity { declared: 0, expected: 2, .. })));
+}
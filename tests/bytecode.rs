@@ -0,0 +1,159 @@
+use advrs::symbol::*;
+use advrs::syntax::*;
+use advrs::lexer::*;
+use advrs::parser::*;
+use advrs::class_table::*;
+use advrs::opcode::*;
+use advrs::bytecode::*;
+
+const SOURCE: &str = "target: 'indev'
+
+entrypoint: 'Program'
+
+class True extends Object:
+end
+
+class False extends Object:
+end
+
+class Program extends Object:
+    field flag
+
+    method main():
+        this.flag = True
+        while this.flag:
+            this.flag = this.toggle(this.flag)
+        end
+    end
+
+    method toggle(value):
+        if value is True:
+            return False
+        end
+        return True
+    end
+end
+
+class Child extends Program:
+    method toggle(value):
+        return False
+    end
+end
+";
+
+fn compile_source() -> (Metadata, ClassTable, Vec<CompiledClass>) {
+    let (metadata, classes) = parse("test.adv", tokenize("test.adv", SOURCE).unwrap()).unwrap();
+    let table = ClassTable::create(&[builtin_classes(), classes].concat()).unwrap();
+    let compiled = compile(&table, &mut vec![]).unwrap();
+    (metadata, table, compiled)
+}
+
+#[test]
+fn round_trip() {
+    let (metadata, table, compiled) = compile_source();
+    let (loaded_metadata, loaded_table, loaded_compiled) = deserialize(&serialize(&metadata, &table, &compiled)).unwrap();
+
+    assert_eq!(loaded_metadata, metadata);
+    assert_eq!(loaded_table.map, table.map);
+    assert_eq!(loaded_table.truth, table.truth);
+    assert_eq!(loaded_table.lie, table.lie);
+    assert_eq!(loaded_compiled, compiled);
+}
+
+#[test]
+fn rejects_invalid_files() {
+    let (metadata, table, compiled) = compile_source();
+    let bytes = serialize(&metadata, &table, &compiled);
+
+    assert!(deserialize(b"target: 'indev'").is_err());
+    assert!(deserialize(&bytes[..bytes.len() - 1]).is_err());
+
+    let mut corrupted = bytes.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(deserialize(&corrupted).is_err());
+
+    let mut future = bytes.clone();
+    future[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert!(deserialize(&future).is_err());
+
+    let other_target = Metadata {
+        target: "v0".to_string(),
+        ..metadata
+    };
+    assert!(deserialize(&serialize(&other_target, &table, &compiled)).is_err());
+}
+
+#[test]
+fn verifier_rejects_malformed_methods() {
+    let (_, table, _) = compile_source();
+    let fields = vec![Symbol::intern("flag")];
+    let method = |mut body: Vec<OpCode>| CompiledMethod {
+        name: Symbol::intern("test"),
+        spans: vec![Span::builtin(); body.len()],
+        caches: vec![InlineCache::default(); number_cache_sites(&mut body)],
+        body: Some(body),
+        params_count: 0,
+        locals_size: 1,
+        unit: Symbol::intern("test.adv"),
+        private: false,
+    };
+
+    assert!(verify_method(&table, &fields, &method(vec![OpCode::This, OpCode::GetFI(0), OpCode::Return])).is_ok());
+
+    let invalid = [
+        vec![OpCode::Pop],
+        vec![OpCode::This, OpCode::This, OpCode::Return],
+        vec![OpCode::This],
+        vec![OpCode::This, OpCode::Jump(true, 5)],
+        vec![OpCode::GetV(1), OpCode::Return],
+        vec![OpCode::New(table.classes.len()), OpCode::Return],
+        vec![OpCode::This, OpCode::GetFI(1), OpCode::Return],
+        vec![OpCode::New(0), OpCode::GetFI(0), OpCode::Return],
+        vec![OpCode::This, OpCode::Jump(false, 3), OpCode::This, OpCode::Pop],
+        vec![OpCode::Recurse],
+    ];
+    for body in invalid {
+        assert!(verify_method(&table, &fields, &method(body.clone())).is_err(), "{body:?}");
+    }
+
+    // Every site needs its own cache
+    let shared = CompiledMethod {
+        caches: vec![InlineCache::default()],
+        ..method(vec![OpCode::This, OpCode::GetF(fields[0], 0), OpCode::This, OpCode::GetF(fields[0], 0), OpCode::Equals, OpCode::Return])
+    };
+    assert!(verify_method(&table, &fields, &shared).is_err());
+}
+
+#[test]
+fn tail_calls_have_the_stack_effect_of_a_call() {
+    for params_count in 0..3 {
+        assert_eq!(OpCode::Recurse.stack_diff(params_count), OpCode::Call(0, params_count, 0).stack_diff(params_count));
+    }
+}
+
+#[test]
+fn rejects_fields_that_dont_match_the_class_table() {
+    let (metadata, table, compiled) = compile_source();
+    let child = table.get_class_id("Child".into()).unwrap();
+    let null = table.null.0;
+
+    let mut missing = compiled.clone();
+    missing[child].fields.clear();
+    assert!(deserialize(&serialize(&metadata, &table, &missing)).is_err());
+
+    let mut reordered = compiled.clone();
+    reordered[child].fields.push(Symbol::intern("extra"));
+    reordered[child].fields.reverse();
+    assert!(deserialize(&serialize(&metadata, &table, &reordered)).is_err());
+
+    // A declared field makes the layout consistent, but `Null` still can't have any
+    let mut fielded_table = table.clone();
+    fielded_table.classes[null].own_fields.push(Symbol::intern("next"));
+    fielded_table.classes[null].field_spans.push(Span::builtin());
+    let mut fielded = compiled.clone();
+    fielded[null].fields.push(Symbol::intern("next"));
+    let Err(error) = deserialize(&serialize(&metadata, &fielded_table, &fielded)) else {
+        panic!("Expected an error");
+    };
+    assert!(error.to_string().contains("'Null' can't have fields"), "{error}");
+}
@@ -0,0 +1,80 @@
+use std::fs;
+use std::process::Command;
+
+const SOURCE: &str = "target: 'indev'
+entrypoint: 'Main'
+class Main extends Object:
+    method main():
+MAIN
+    end
+
+    method speak(animal):
+        return animal.sound()
+    end
+
+    method get(box):
+        return box.value
+    end
+end
+class A extends Object:
+    method sound():
+        return this
+    end
+end
+class B extends A:
+end
+class C extends A:
+end
+class D extends A:
+end
+class E extends A:
+end
+class Box extends Object:
+    field value
+end
+class Crate extends Box:
+end
+";
+
+// Runs a `main` that calls each method with a new instance of its class, and returns the printed stats
+// Every one of these calls is a site of its own in `main`, so each of them adds a miss to the `Call` stats
+fn cache_stats(name: &str, calls: &[(&str, &str)]) -> String {
+    let main = calls.iter().map(|(method, class)| format!("        this.{method}({class})\n")).collect::<String>();
+    let path = std::env::temp_dir().join(format!("advrs-cache-stats-{name}-{}.adv", std::process::id()));
+    fs::write(&path, SOURCE.replace("MAIN\n", &main)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_advrs")).arg("run").arg(&path).arg("--cache-stats").output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn polymorphic_sites_hit_for_every_cached_class() {
+    let speak = ["A", "B", "A", "B", "A"].map(|class| ("speak", class));
+    let get = ["Box", "Crate", "Crate"].map(|class| ("get", class));
+    let stats = cache_stats("polymorphic", &[&speak[..], &get[..]].concat());
+
+    // The site in `speak` misses for the first `A` and `B` only
+    assert!(stats.contains("Inline caches | Call: 3 hits, 10 misses "), "{stats}");
+    assert!(stats.contains("Inline caches | GetF: 1 hits, 2 misses "), "{stats}");
+    assert!(stats.contains("Inline caches | SetF: 0 hits, 0 misses "), "{stats}");
+}
+
+#[test]
+fn megamorphic_sites_keep_missing() {
+    // One more class than the cache has entries, so each one gets evicted right before it's used again
+    let speak = ["A", "B", "C", "D", "E", "A", "B", "C", "D", "E"].map(|class| ("speak", class));
+    let stats = cache_stats("megamorphic", &speak);
+    assert!(stats.contains("Inline caches | Call: 0 hits, 20 misses "), "{stats}");
+}
+
+#[test]
+fn cache_stats_are_printed_after_running() {
+    let stats = cache_stats("printed", &[("speak", "A"), ("speak", "A"), ("speak", "B")]);
+    // Each call to `speak` is its own site, the only hit is the second `A` inside of it
+    assert_eq!(stats, "\
+Inline caches | Call: 1 hits, 5 misses (16.67% hit rate)
+Inline caches | GetF: 0 hits, 0 misses (100.00% hit rate)
+Inline caches | SetF: 0 hits, 0 misses (100.00% hit rate)
+");
+}
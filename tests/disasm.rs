@@ -0,0 +1,68 @@
+use advrs::error::*;
+use advrs::lexer::*;
+use advrs::parser::*;
+use advrs::class_table::*;
+use advrs::opcode::*;
+use advrs::disasm::*;
+
+const SOURCE: &str = "target: 'indev'
+class True extends Object:
+end
+class False extends Object:
+end
+class Counter extends Object:
+    field count
+
+    method step(x):
+        while x:
+            this.count = x
+            x = False
+        end
+        return this.step(this.count)
+    end
+end
+class Child extends Counter:
+    field extra
+end
+";
+
+#[test]
+fn disassembles_classes_and_methods() {
+    let (_, classes) = parse("test.adv", tokenize("test.adv", SOURCE).unwrap()).unwrap();
+    let table = ClassTable::create(&[builtin_classes(), classes].concat()).unwrap();
+    let compiled = compile(&table, &mut vec![]).unwrap();
+
+    assert_eq!(disassemble(&table, &compiled, Some("Counter"), None).unwrap(), "\
+class Counter (id 3) extends Object, type range 3..5
+    field 0: count
+    method step/1 (locals: 1)
+           0  GetV 0                                   ; test.adv:10:15
+           1  JumpIfFalse L1                           ; test.adv:10:9
+      L0:
+           2  This                                     ; test.adv:11:13
+           3  GetV 0                                   ; test.adv:11:26
+           4  SetFI 0 (count)                          ; test.adv:11:24
+           5  New 2 (False)                            ; test.adv:12:17
+           6  SetV 0                                   ; test.adv:12:15
+           7  GetV 0                                   ; test.adv:10:15
+           8  JumpIfTrue L0                            ; test.adv:10:9
+      L1:
+           9  This                                     ; test.adv:14:16
+          10  This                                     ; test.adv:14:26
+          11  GetFI 0 (count)                          ; test.adv:14:31
+          12  Recurse (tail call)                      ; test.adv:14:21
+          13  Return                                   ; test.adv:14:9
+
+");
+
+    assert_eq!(disassemble(&table, &compiled, Some("Child"), Some("step")).unwrap(), "\
+class Child (id 4) extends Counter, type range 4..5
+    field 0: count (inherited from Counter)
+    field 1: extra
+    method step/1 (inherited from Counter)
+
+");
+    assert!(matches!(disassemble(&table, &compiled, Some("Missing"), None), Err(AdvError::UnknownClass { .. })));
+    assert!(matches!(disassemble(&table, &compiled, Some("Child"), Some("missing")), Err(AdvError::UnknownMethod { class: Some(_), .. })));
+    assert!(matches!(disassemble(&table, &compiled, None, Some("missing")), Err(AdvError::UnknownMethod { class: None, .. })));
+}
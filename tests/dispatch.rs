@@ -0,0 +1,67 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+
+use advrs::lexer::*;
+use advrs::parser::*;
+use advrs::class_table::*;
+use advrs::opcode::*;
+
+const SOURCE: &str = "target: 'indev'
+class Animal extends Object:
+    method speak():
+        return this.sound()
+    end
+
+    method sound():
+        return Animal
+    end
+end
+class Dog extends Animal:
+    method sound():
+        return Dog
+    end
+end
+class Rock extends Object:
+end
+";
+
+fn write_program(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("advrs-dispatch-{name}-{}.adv", std::process::id()));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn vtables_follow_overrides() {
+    let path = write_program("overrides", SOURCE);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_advrs")).arg("repl").arg(&path).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"return Animal.speak()\nreturn Dog.speak()\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.split("=> ").skip(1).map(|s| s.lines().next().unwrap()).collect::<Vec<_>>(), ["Animal", "Dog"], "{}", String::from_utf8_lossy(&output.stderr));
+
+    // Inherited methods are shared, overridden ones take the slot of the parent's
+    let (_, classes) = parse("test.adv", tokenize("test.adv", SOURCE).unwrap()).unwrap();
+    let table = ClassTable::create(&[builtin_classes(), classes].concat()).unwrap();
+    let compiled = compile(&table, &mut vec![]).unwrap();
+    let [animal, dog, rock] = ["Animal", "Dog", "Rock"].map(|name| &compiled[table.get_class_id(name.into()).unwrap()]);
+    let [speak, sound] = ["speak", "sound"].map(|name| table.selectors.get(name.into()).unwrap());
+    assert!(Rc::ptr_eq(animal.lookup(speak).unwrap(), dog.lookup(speak).unwrap()));
+    assert!(!Rc::ptr_eq(animal.lookup(sound).unwrap(), dog.lookup(sound).unwrap()));
+    let index = |class: &CompiledClass| class.methods.iter().position(|m| m.name == "sound");
+    assert_eq!(index(animal), index(dog));
+    assert!(rock.lookup(speak).is_none());
+}
+
+#[test]
+fn dispatch_errors_name_the_method() {
+    let source = format!("{}class Main extends Object:\n    method main():\n        return Rock.speak()\n    end\nend\n", SOURCE.replacen("\n", "\nentrypoint: 'Main'\n", 1));
+    let path = write_program("errors", &source);
+    let output = Command::new(env!("CARGO_BIN_EXE_advrs")).arg("run").arg(&path).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Type 'Rock' doesn't define method 'speak'"), "{stderr}");
+}
@@ -0,0 +1,327 @@
+use std::fs;
+use std::process::{Command, Output};
+
+use advrs::syntax::*;
+use advrs::error::*;
+use advrs::lexer::*;
+use advrs::parser::*;
+use advrs::class_table::*;
+use advrs::opcode::*;
+use advrs::interpreter::*;
+use advrs::builtins::*;
+use advrs::gc::*;
+
+fn compile_source(source: &str) -> Result<(ClassTable, Vec<CompiledClass>)> {
+    compile_files(&[("test.adv", source)])
+}
+
+fn compile_files(files: &[(&str, &str)]) -> Result<(ClassTable, Vec<CompiledClass>)> {
+    let mut classes = builtin_classes();
+    for (name, source) in files {
+        classes.extend(parse(name, tokenize(name, source)?)?.1);
+    }
+    let table = ClassTable::create(&classes)?;
+    let compiled = compile(&table, &mut vec![])?;
+    Ok((table, compiled))
+}
+
+#[test]
+fn errors_are_typed_by_stage() {
+    let header = "target: 'indev'\n";
+
+    let error = compile_source(&format!("{header}class A extends Object:\n    $\nend\n")).unwrap_err();
+    assert!(matches!(error, AdvError::Lex { span: Span { line: 3, column: 5, .. }, unexpected: Some('$') }), "{error:?}");
+
+    let error = compile_source(&format!("{header}class A extends Object:\n    field\n")).unwrap_err();
+    assert!(matches!(error, AdvError::Parse { span: Span { line: 3, .. }, .. }), "{error:?}");
+
+    let error = compile_source("target: 'old'\n").unwrap_err();
+    assert!(matches!(&error, AdvError::VersionMismatch { target } if target == "old"), "{error:?}");
+
+    let error = compile_source(&format!("{header}class A extends B:\nend\n")).unwrap_err();
+    assert!(matches!(error, AdvError::InvalidParent { class, parent, .. } if class == "A" && parent == "B"), "{error:?}");
+
+    let error = compile_source(&format!("{header}class A extends Object:\n    method main():\n        x = y\n    end\nend\n")).unwrap_err();
+    assert!(matches!(error, AdvError::Compile { class: Some(class), method: Some(method), .. } if class == "A" && method == "main"), "{error:?}");
+}
+
+#[test]
+fn runtime_errors_carry_a_backtrace() {
+    let (table, compiled) = compile_source("target: 'indev'
+class A extends Object:
+    method main():
+        this.step()
+    end
+
+    method step():
+        Null.missing()
+    end
+end
+").unwrap();
+    let entrypoint = table.get_class_id("A".into()).unwrap();
+
+    let mut stack = VmStack::new(1024);
+    let mut gc = GC::new(GcConfig::default());
+    let ctx = RunCtx::new(&mut gc, table, compiled, &[], entrypoint, Builtins::default()).unwrap();
+    stack.push(ctx.entrypoint).unwrap();
+    let main = ctx.classes[entrypoint].methods.iter().find(|m| m.name == "main").unwrap();
+
+    let Err(AdvError::Runtime(error)) = run(&ctx, &mut gc, &mut stack, main) else {
+        panic!("Expected a runtime error");
+    };
+    assert!(matches!(error.kind, RuntimeErrorKind::UndefinedMethod { class, method } if class == "Null" && method == "missing"), "{error:?}");
+    assert_eq!(error.span.as_ref().map(|s| s.line), Some(8));
+    let methods = error.backtrace.frames.iter().map(|(f, _)| f.method.as_str()).collect::<Vec<_>>();
+    assert_eq!(methods, ["step", "main"]);
+}
+
+#[test]
+fn duplicates_report_both_sites() {
+    let error = compile_source("target: 'indev'
+class A extends Object:
+    field x
+    field x
+end
+class A extends Object:
+end
+").unwrap_err();
+    let AdvError::Multiple(errors) = error else {
+        panic!("Expected multiple errors, got {error:?}");
+    };
+    assert!(matches!(&errors[0], AdvError::DuplicateField { span, previous, field, .. } if span.line == 4 && previous.line == 3 && *field == "x"), "{errors:?}");
+    assert!(matches!(&errors[1], AdvError::DuplicateClass { span, previous, .. } if span.line == 6 && previous.line == 2), "{errors:?}");
+
+    let error = compile_source("target: 'indev'
+class A extends Object:
+    field x
+end
+class B extends A:
+    field x
+end
+").unwrap_err();
+    assert!(matches!(&error, AdvError::ShadowedField { span, inherited, class, parent, .. } if span.line == 6 && inherited.line == 3 && *class == "B" && *parent == "A"), "{error:?}");
+}
+
+#[test]
+fn private_access_reports_both_sites() {
+    let lib = ("lib.adv", "target: 'indev'
+private class Hidden extends Object:
+end
+class Lib extends Object:
+    private field secret
+    private method helper():
+        this.secret = Hidden
+    end
+end
+");
+    assert!(compile_files(&[lib]).is_ok());
+
+    let error = compile_files(&[lib, ("main.adv", "target: 'indev'\nclass Main extends Hidden:\nend\n")]).unwrap_err();
+    assert!(matches!(&error, AdvError::PrivateAccess { span, definition, member: Member::Class, .. } if *span.file == *"main.adv" && definition.line == 2), "{error:?}");
+
+    let error = compile_files(&[lib, ("main.adv", "target: 'indev'\nclass Main extends Lib:\n    method main():\n        this.helper()\n    end\nend\n")]).unwrap_err();
+    assert!(matches!(&error, AdvError::PrivateAccess { span, definition, member: Member::Method, unit, .. } if span.line == 4 && definition.line == 6 && *unit == "lib.adv"), "{error:?}");
+
+    let error = compile_files(&[lib, ("main.adv", "target: 'indev'\nclass Main extends Object:\n    method main(lib):\n        lib.secret = lib\n    end\nend\n")]).unwrap_err();
+    assert!(matches!(&error, AdvError::PrivateAccess { span, definition, member: Member::Field, .. } if span.line == 4 && definition.line == 5), "{error:?}");
+}
+
+#[test]
+fn private_members_are_checked_when_used() {
+    // `Main` declares public members with the same names, so the compiler can't reject these accesses
+    let dir = std::env::temp_dir().join(format!("advrs-private-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("lib.adv"), "target: 'indev'
+class Vault extends Object:
+    private field secret
+
+    private method open():
+        return this.secret
+    end
+end
+").unwrap();
+    let run = |call: &str| {
+        fs::write(dir.join("main.adv"), format!("target: 'indev'
+import: 'lib.adv'
+entrypoint: 'Main'
+class Main extends Object:
+    field secret
+
+    method main():
+        this.steal(this)
+        this.peek(this)
+        this.{call}(Vault)
+        return this
+    end
+
+    method open():
+        return this
+    end
+
+    method steal(v):
+        v.secret = this
+        return v
+    end
+
+    method peek(v):
+        return v.open()
+    end
+end
+")).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_advrs")).arg("run").arg(dir.join("main.adv")).output().unwrap();
+        assert!(!output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    // The public members are reachable through the same call sites, so only the last call fails
+    let unit = dir.join("lib.adv");
+    let stderr = run("steal");
+    assert!(stderr.contains(&format!("Field 'secret' is private to '{}'", unit.display())), "{stderr}");
+    let stderr = run("peek");
+    assert!(stderr.contains(&format!("Method 'open' is private to '{}'", unit.display())), "{stderr}");
+}
+
+// A list of 2^15 nodes, built by doubling it, so only recursion that doesn't use the native stack can get through it
+const LIST: &str = "class Main extends Object:
+    method main():
+        list = Node
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        list.append(list.copy())
+        return list.RESULT()
+    end
+end
+class Node extends Object:
+    field next
+
+    method append(other):
+        if this.next is Null:
+            this.next = other
+            return this
+        end
+        this.next.append(other)
+        return this
+    end
+
+    method copy():
+        result = Node
+        if this.next is Null:
+            return result
+        end
+        result.next = this.next.copy()
+        return result
+    end
+
+    method last():
+        if this.next is Null:
+            return this
+        end
+        last = this.next.last()
+        return last
+    end
+
+    method ping():
+        result = this.next.pong()
+        return result
+    end
+
+    method pong():
+        result = this.next.ping()
+        return result
+    end
+end
+class True extends Object:
+end
+class False extends Object:
+end
+";
+
+// Runs `main` of the list program, with `method` called on the whole list at the end
+fn run_list(method: &str) -> Output {
+    let path = std::env::temp_dir().join(format!("advrs-list-{method}-{}.adv", std::process::id()));
+    fs::write(&path, format!("target: 'indev'\nentrypoint: 'Main'\n{}", LIST.replace("RESULT", method))).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_advrs")).arg("run").arg(&path).output().unwrap()
+}
+
+#[test]
+fn deep_non_tail_recursion_doesnt_use_the_native_stack() {
+    let output = run_list("last");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn long_backtraces_are_capped() {
+    // Alternating frames can't be collapsed, so most of them are left out
+    let output = run_list("ping");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Runtime error: "), "{stderr}");
+    assert_eq!(stderr.lines().filter(|l| l.starts_with("    at ")).count(), AdvBacktrace::MAX_RUNS, "{stderr}");
+    // Every node has a frame, along with `main`
+    assert!(stderr.contains(&format!("\n    ... {} more frames\n", (1 << 15) + 1 - AdvBacktrace::MAX_RUNS)), "{stderr}");
+}
+
+// `main` calls `CALL`, both of the methods that it can be replaced with recurse forever
+const RECURSION: &str = "target: 'indev'
+entrypoint: 'Main'
+class Main extends Object:
+    method main():
+        this.CALL()
+        return this
+    end
+
+    method down():
+        this.down()
+        return this
+    end
+
+    method ping():
+        this.pong()
+        return this
+    end
+
+    method pong():
+        this.ping()
+        return this
+    end
+end
+";
+
+fn run_recursion(call: &str, args: &[&str]) -> String {
+    let path = std::env::temp_dir().join(format!("advrs-recursion-{call}-{}.adv", std::process::id()));
+    fs::write(&path, RECURSION.replace("CALL", call)).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_advrs")).arg("run").arg(&path).args(args).output().unwrap();
+    assert!(!output.status.success());
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn stack_size_limits_the_depth() {
+    for stack_size in [1000, 4000] {
+        let stderr = run_recursion("down", &["--stack-size", &stack_size.to_string()]);
+        assert!(stderr.starts_with("Runtime error: "), "{stderr}");
+        assert!(stderr.contains(": adv stack overflow\n"), "{stderr}");
+        // Every call to `down` takes up one more slot, on top of the one of `main`
+        assert!(stderr.contains(&format!("\n    ... repeated {} times", stack_size - 3)), "{stderr}");
+    }
+}
+
+#[test]
+fn overflowing_the_default_stack_keeps_the_backtrace_small() {
+    let stderr = run_recursion("ping", &[]);
+    assert!(stderr.contains(": adv stack overflow\n"), "{stderr}");
+    assert_eq!(stderr.lines().filter(|l| l.starts_with("    at ")).count(), AdvBacktrace::MAX_RUNS, "{stderr}");
+    assert!(stderr.contains(" more frames\n"), "{stderr}");
+}
@@ -0,0 +1,159 @@
+use advrs::gc::*;
+use advrs::error::*;
+use advrs::interpreter::*;
+
+const NODE_CLASS: usize = 1;
+
+// Too slow for Miri, the smaller tests below cover the same code
+#[test]
+#[cfg_attr(miri, ignore)]
+fn collect_million_node_list() {
+    let mut stack = VmStack::new(16);
+    let mut gc = GC::new(GcConfig::default());
+    stack.push(Object::TRUE_NULL).unwrap();
+
+    // Each node has a single field pointing at the next one, the head is kept on the stack
+    for _ in 0..1_000_000 {
+        let node = gc.alloc(NODE_CLASS, 1).unwrap();
+        gc.set(node, 0, stack.get(0).unwrap()).unwrap();
+        stack.set(0, node).unwrap();
+    }
+
+    let stats = gc.collect(stack.live());
+    assert_eq!(stats.after, 1_000_000);
+
+    let mut length = 0;
+    let mut node = stack.get(0).unwrap();
+    while node != Object::TRUE_NULL {
+        length += 1;
+        node = gc.get(node, 0).unwrap();
+    }
+    assert_eq!(length, 1_000_000);
+
+    stack.set(0, Object::TRUE_NULL).unwrap();
+    let stats = gc.collect(stack.live());
+    assert_eq!(stats.collected(), 1_000_000);
+    assert_eq!(gc.allocation_count(), 0);
+}
+
+#[test]
+fn max_heap_size_is_enforced() {
+    let mut stack = VmStack::new(16);
+    let mut gc = GC::new(GcConfig {
+        initial_heap_size: 8,
+        max_heap_size: Some(16),
+        ..GcConfig::default()
+    });
+    stack.push(Object::TRUE_NULL).unwrap();
+
+    for _ in 0..16 {
+        if gc.should_collect() {
+            gc.collect(stack.live());
+        }
+        let node = gc.alloc(NODE_CLASS, 1).unwrap();
+        gc.set(node, 0, stack.get(0).unwrap()).unwrap();
+        stack.set(0, node).unwrap();
+    }
+
+    assert!(gc.should_collect());
+    gc.collect(stack.live());
+    assert!(gc.alloc(NODE_CLASS, 1).is_err());
+
+    // Once the list is unreachable, there's room again
+    stack.set(0, Object::TRUE_NULL).unwrap();
+    gc.collect(stack.live());
+    assert!(gc.alloc(NODE_CLASS, 1).is_ok());
+}
+
+#[test]
+fn collected_objects_stay_unreachable() {
+    let mut gc = GC::new(GcConfig::default());
+    let kept = gc.alloc(NODE_CLASS, 2).unwrap();
+    let dropped = gc.alloc(NODE_CLASS, 2).unwrap();
+    gc.set(kept, 1, dropped).unwrap();
+    gc.set(kept, 1, Object::TRUE_NULL).unwrap();
+
+    assert_eq!(gc.collect(&[kept]).after, 1);
+    assert!(gc.is_live(kept));
+    assert!(!gc.is_live(dropped));
+    assert!(matches!(gc.get(dropped, 0), Err(RuntimeErrorKind::DanglingObject)));
+
+    // The new object reuses the slot, but the old reference can't see it
+    let reused = gc.alloc(NODE_CLASS, 2).unwrap();
+    gc.set(reused, 0, kept).unwrap();
+    assert!(matches!(gc.set(dropped, 0, kept), Err(RuntimeErrorKind::DanglingObject)));
+    assert_eq!(gc.get(reused, 0).unwrap(), kept);
+    assert_ne!(reused, dropped);
+}
+
+#[test]
+fn field_indices_are_checked() {
+    let mut gc = GC::new(GcConfig::default());
+    let obj = gc.alloc(NODE_CLASS, 3).unwrap();
+    assert_eq!(gc.fields(obj).unwrap(), [Object::TRUE_NULL; 3]);
+    assert!(matches!(gc.get(obj, 3), Err(RuntimeErrorKind::FieldOutOfBounds { index: 3, len: 3 })));
+
+    // Field-less objects don't live on the heap, but each of them is still distinct
+    let empty = gc.alloc(NODE_CLASS, 0).unwrap();
+    assert_ne!(empty, gc.alloc(NODE_CLASS, 0).unwrap());
+    assert_ne!(empty, Object::TRUE_NULL);
+    assert!(matches!(gc.set(empty, 0, obj), Err(RuntimeErrorKind::FieldOutOfBounds { index: 0, len: 0 })));
+
+    // An object is only valid on a heap that has allocated its slot, a fresh one hasn't allocated any
+    let other = GC::new(GcConfig::default());
+    assert!(matches!(other.get(obj, 0), Err(RuntimeErrorKind::DanglingObject)));
+}
+
+#[test]
+fn handles_are_roots() {
+    let mut gc = GC::new(GcConfig::default());
+    let obj = gc.alloc(NODE_CLASS, 1).unwrap();
+    let child = gc.alloc(NODE_CLASS, 1).unwrap();
+    gc.set(obj, 0, child).unwrap();
+
+    let handle = gc.handle(obj);
+    let copy = handle.clone();
+    drop(handle);
+    assert_eq!(gc.collect(&[]).after, 2);
+    assert_eq!(gc.get(copy.object(), 0).unwrap(), child);
+
+    drop(copy);
+    assert_eq!(gc.collect(&[]).after, 0);
+}
+
+#[test]
+fn remapping_reaches_every_object() {
+    let mut gc = GC::new(GcConfig::default());
+    let tail = gc.alloc(NODE_CLASS, 1).unwrap();
+    let leaf = gc.alloc(2, 0).unwrap();
+    gc.set(tail, 0, leaf).unwrap();
+    gc.pin(tail);
+
+    // Long enough that walking it recursively would be a problem outside of Miri
+    let length = if cfg!(miri) { 1_000 } else { 1_000_000 };
+    let mut head = tail;
+    for _ in 1..length {
+        let node = gc.alloc(NODE_CLASS, 1).unwrap();
+        gc.set(node, 0, head).unwrap();
+        head = node;
+    }
+
+    let mut roots = [head, Object::TRUE_NULL];
+    gc.remap_classes(&mut roots, &[0, 3, 4]);
+    assert_eq!(roots[1], Object::TRUE_NULL);
+
+    let mut node = roots[0];
+    let mut last = node;
+    for _ in 0..length {
+        assert_eq!(node.class(), 3);
+        last = node;
+        node = gc.get(node, 0).unwrap();
+    }
+    assert_eq!(node.class(), 4);
+
+    // The pin of the tail was remapped as well, so it still keeps it alive and can be undone
+    assert_eq!(gc.collect(&[]).after, 1);
+    assert!(gc.is_live(last));
+    gc.unpin(last);
+    assert_eq!(gc.collect(&[]).after, 0);
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::path::PathBuf;
+
+use advrs::syntax::*;
+use advrs::error::*;
+use advrs::loader::*;
+
+// A fresh directory with the given files, `name` has to be unique between tests
+fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("advrs-{name}-{}", std::process::id()));
+    _ = fs::remove_dir_all(&dir);
+    for (path, contents) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn transitive_imports_are_loaded_once() {
+    // main imports two libraries, which both import the same base library through different paths
+    let dir = write_files("diamond", &[
+        ("main.adv", "import: 'lib/left.adv'\nimport: 'lib/right.adv'\nentrypoint: 'Main'\nclass Main extends Object:\nend\n"),
+        ("lib/left.adv", "import: 'base.adv'\nclass Left extends Base:\nend\n"),
+        ("lib/right.adv", "import: '../lib/base.adv'\nclass Right extends Base:\nend\n"),
+        ("lib/base.adv", "class Base extends Object:\nend\n"),
+    ]);
+
+    let (metadata, classes) = load(&dir.join("main.adv"), &[]).unwrap();
+    assert_eq!(metadata.entrypoints, ["Main"]);
+    let names = classes.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["Main", "Left", "Base", "Right"]);
+}
+
+#[test]
+fn import_cycles_are_reported() {
+    let dir = write_files("cycle", &[
+        ("main.adv", "import: 'a.adv'\n"),
+        ("a.adv", "import: 'b.adv'\n"),
+        ("b.adv", "import: 'a.adv'\n"),
+    ]);
+
+    let Err(AdvError::ImportCycle { chain }) = load(&dir.join("main.adv"), &[]) else {
+        panic!("Expected an import cycle");
+    };
+    let names = chain.iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(names, ["a.adv", "b.adv", "a.adv"]);
+}
+
+#[test]
+fn imports_fall_back_to_the_search_path() {
+    let dir = write_files("search", &[
+        ("project/main.adv", "import: 'shared.adv'\nimport: 'local.adv'\n"),
+        ("project/local.adv", "class Local extends Object:\nend\n"),
+        ("libs/local.adv", "class Wrong extends Object:\nend\n"),
+        ("libs/shared.adv", "class Shared extends Object:\nend\n"),
+    ]);
+    let search_path = [dir.join("empty"), dir.join("libs")];
+
+    let (_, classes) = load(&dir.join("project/main.adv"), &search_path).unwrap();
+    let names = classes.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["Shared", "Local"]);
+
+    let Err(AdvError::ImportNotFound { tried, .. }) = load(&dir.join("project/main.adv"), &search_path[..1]) else {
+        panic!("Expected a missing import");
+    };
+    assert_eq!(tried, [dir.join("project/shared.adv"), dir.join("empty/shared.adv")]);
+}
+
+#[test]
+fn modules_keep_class_names_apart() {
+    let dir = write_files("modules", &[
+        ("main.adv", "import: 'a.adv'\nimport: 'b.adv'\nuse: 'b::List as Other'\nentrypoint: 'Main'\nclass Main extends a::List:\n    method other():\n        return Other\n    end\nend\n"),
+        ("a.adv", "module: 'a'\nclass List extends Object:\nend\nclass Node extends List:\nend\n"),
+        ("b.adv", "module: 'b'\nclass List extends Object:\nend\n"),
+    ]);
+
+    let (metadata, classes) = load(&dir.join("main.adv"), &[]).unwrap();
+    assert_eq!(metadata.entrypoints, ["Main"]);
+    let names = classes.iter().map(|c| (c.name.as_str(), c.module.map(|m| m.as_str()), c.parent.map(|p| p.as_str()))).collect::<Vec<_>>();
+    assert_eq!(names, [
+        ("Main", None, Some("a::List")),
+        ("a::List", Some("a"), Some("Object")),
+        ("a::Node", Some("a"), Some("a::List")),
+        ("b::List", Some("b"), Some("Object")),
+    ]);
+    let body = classes[0].own_methods[0].body.as_ref().unwrap();
+    assert!(matches!(&body[0].kind, StatementKind::Return(e) if e.kind == ExpressionKind::Get("b::List".into())));
+
+    let dir = write_files("bad-use", &[
+        ("main.adv", "import: 'a.adv'\nuse: 'a::Missing'\n"),
+        ("a.adv", "module: 'a'\n"),
+    ]);
+    assert!(matches!(load(&dir.join("main.adv"), &[]), Err(AdvError::InvalidMetadata(_))));
+}
+
+#[test]
+fn singletons_of_modules_are_qualified_and_merged() {
+    let dir = write_files("singletons", &[
+        ("main.adv", "import: 'a.adv'\nsingleton: 'Local'\nclass Local extends Object:\nend\n"),
+        ("a.adv", "module: 'a'\nsingleton: 'Token'\nclass Token extends Object:\nend\n"),
+    ]);
+
+    let (metadata, _) = load(&dir.join("main.adv"), &[]).unwrap();
+    assert_eq!(metadata.singletons, ["Local", "a::Token"]);
+}
+
+#[test]
+fn sources_without_a_directory_import_from_the_working_directory() {
+    for name in ["", "/"] {
+        let source = (name.to_owned(), "import: 'advrs-missing.adv'\n".to_owned());
+        let Err(AdvError::ImportNotFound { tried, .. }) = load_roots(&[], &[source], &[]) else {
+            panic!("Expected a missing import");
+        };
+        assert_eq!(tried, [PathBuf::from("./advrs-missing.adv")]);
+    }
+}
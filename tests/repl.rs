@@ -0,0 +1,101 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Feeds `input` to a repl without a program, returns its stdout and stderr
+fn repl(input: &str) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_advrs")).arg("repl").stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+}
+
+// The values that the input returned, in order
+fn results(stdout: &str) -> Vec<&str> {
+    stdout.split("=> ").skip(1).map(|s| s.lines().next().unwrap()).collect()
+}
+
+#[test]
+fn classes_can_be_used_once_defined() {
+    let (stdout, stderr) = repl("class True extends Object:
+end
+class False extends Object:
+end
+class A extends Object:
+    field value
+
+    method get():
+        return this
+    end
+end
+a = A
+return a.get()
+class B extends A:
+    method get():
+        this.value = A
+        return this.value
+    end
+end
+b = B
+return b.get()
+return a.get()
+");
+    assert!(stdout.contains("Defined True\n") && stdout.contains("Defined A\n") && stdout.contains("Defined B\n"), "{stdout}");
+    assert_eq!(results(&stdout), ["A", "A", "A"], "{stderr}");
+    assert!(stderr.is_empty(), "{stderr}");
+}
+
+#[test]
+fn existing_objects_follow_new_classes() {
+    // Defining `B` moves the ids of the other classes around, `a` was created before that
+    let (stdout, stderr) = repl("class True extends Object:
+end
+class False extends Object:
+end
+class A extends Object:
+end
+a = A
+class B extends A:
+end
+return a is A
+return a is B
+return B is A
+return a = a
+");
+    assert_eq!(results(&stdout), ["True", "False", "True", "True"], "{stderr}");
+}
+
+#[test]
+fn methods_nobody_defines_yet_fail_at_runtime() {
+    let (stdout, stderr) = repl("class A extends Object:
+end
+a = A
+return a.later()
+class B extends A:
+    method later():
+        return this
+    end
+end
+return B.later()
+return a.later()
+");
+    assert_eq!(results(&stdout), ["B"], "{stderr}");
+    let errors = stderr.lines().filter(|l| l.starts_with("Error: Runtime error: ")).collect::<Vec<_>>();
+    assert_eq!(errors.len(), 2, "{stderr}");
+    assert!(errors.iter().all(|e| e.ends_with("Type 'A' doesn't define method 'later'")), "{stderr}");
+}
+
+#[test]
+fn existing_classes_arent_compiled_again() {
+    // The warning comes from compiling `A`, which only happens once
+    let (stdout, stderr) = repl("class A extends Object:
+    method check(x):
+        return x is Missing
+    end
+end
+class B extends Object:
+end
+");
+    assert!(stdout.contains("Defined B\n"), "{stdout}");
+    assert_eq!(stderr.matches("'Missing'").count(), 1, "{stderr}");
+}
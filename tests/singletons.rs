@@ -0,0 +1,75 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const CLASSES: &str = "entrypoint: 'Main'
+class Main extends Object:
+end
+class A extends Object:
+end
+class B extends Object:
+end
+class Box extends Object:
+    field value
+end
+class True extends Object:
+end
+class False extends Object:
+end
+";
+
+// Feeds `input` to a repl running the program at `path`
+fn repl(path: &Path, input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_advrs")).arg("repl").arg(path).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn program(name: &str, metadata: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("advrs-singletons-{name}-{}.adv", std::process::id()));
+    fs::write(&path, format!("target: 'indev'\n{metadata}{CLASSES}")).unwrap();
+    path
+}
+
+// Whether two separately created instances of each class are equal
+fn same(path: &Path, classes: &[&str]) -> Vec<bool> {
+    let input = classes.iter().map(|c| format!("return {c} = {c}\n")).collect::<String>();
+    let output = repl(path, &input);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    stdout.split("=> ").skip(1).map(|s| s.starts_with("True")).collect()
+}
+
+#[test]
+fn singleton_instances_are_equal() {
+    let path = program("one", "singleton: 'A'\n");
+    assert_eq!(same(&path, &["A", "B"]), [true, false]);
+
+    let path = program("none", "");
+    assert_eq!(same(&path, &["A"]), [false]);
+}
+
+#[test]
+fn wildcard_marks_every_fieldless_class() {
+    let path = program("wildcard", "singleton: '*'\n");
+    assert_eq!(same(&path, &["A", "B", "Null", "Box"]), [true, true, true, false]);
+}
+
+#[test]
+fn classes_with_fields_are_never_singletons() {
+    let output = repl(&program("fields", "singleton: 'Box'\n"), "");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("'Box'"), "{stderr}");
+}
+
+#[test]
+fn imported_files_can_declare_singletons() {
+    let dir = std::env::temp_dir().join(format!("advrs-imported-singletons-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("lib.adv"), "target: 'indev'\nsingleton: 'Token'\nclass Token extends Object:\nend\n").unwrap();
+    fs::write(dir.join("main.adv"), format!("target: 'indev'\nimport: 'lib.adv'\n{CLASSES}")).unwrap();
+
+    assert_eq!(same(&dir.join("main.adv"), &["Token", "A"]), [true, false]);
+}
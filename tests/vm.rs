@@ -0,0 +1,115 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use advrs::symbol::*;
+use advrs::error::*;
+use advrs::Vm;
+
+// Output that can still be read after the vm took ownership of it
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const SOURCE: &str = "target: 'indev'
+entrypoint: 'Main'
+class Main extends Object:
+    method 'builtin:push_char'(c)
+    method 'builtin:write'()
+
+    method greet(c):
+        this.'builtin:push_char'(c)
+        this.'builtin:write'()
+        return c
+    end
+end
+class Box extends Object:
+    field value
+
+    method get():
+        return this.value
+    end
+end
+class 'a' extends Object:
+end
+";
+
+#[test]
+fn vm_calls_into_loaded_classes() {
+    let output = SharedOutput::default();
+    let mut vm = Vm::builder().source("main.adv", SOURCE).stdout(output.clone()).build().unwrap();
+
+    let main = vm.entrypoint();
+    let a = vm.instantiate("'a'").unwrap();
+    let result = vm.call(&main, "greet", &[a]).unwrap();
+    assert_eq!(vm.class_name(&result), "'a'");
+    assert_eq!(output.0.borrow().as_slice(), b"a");
+
+    let boxed = vm.instantiate("Box").unwrap();
+    let value = vm.field(&boxed, "value").unwrap();
+    assert_eq!(vm.class_name(&value), "Null");
+    vm.set_field(&boxed, "value", &main).unwrap();
+    let value = vm.call(&boxed, "get", &[]).unwrap();
+    assert_eq!(vm.class_name(&value), "Main");
+
+    assert!(matches!(vm.instantiate("Missing"), Err(AdvError::UnknownClass { .. })));
+    assert!(matches!(vm.call(&main, "missing", &[]), Err(AdvError::UnknownMethod { .. })));
+    let error = vm.call(&main, "greet", &[]).unwrap_err();
+    assert!(matches!(&error, AdvError::Runtime(e) if matches!(e.kind, RuntimeErrorKind::ArgumentCount { expected: 1, provided: 0, .. })), "{error:?}");
+}
+
+#[test]
+fn handles_keep_objects_alive() {
+    let mut vm = Vm::builder().source("main.adv", SOURCE).build().unwrap();
+    let outer = vm.instantiate("Box").unwrap();
+    let inner = vm.instantiate("Box").unwrap();
+    vm.set_field(&outer, "value", &inner).unwrap();
+    let copy = outer.clone();
+    assert_eq!(vm.collect().after, 2);
+
+    // `inner` is still reachable through `outer`, which is kept alive by its copy
+    drop(inner);
+    drop(outer);
+    assert_eq!(vm.collect().after, 2);
+    let inner = vm.call(&copy, "get", &[]).unwrap();
+    assert_eq!(vm.class_name(&inner), "Box");
+
+    drop(copy);
+    assert_eq!(vm.collect().after, 1);
+    drop(inner);
+    assert_eq!(vm.collect().after, 0);
+}
+
+#[test]
+fn warnings_are_returned_instead_of_printed() {
+    let source = "target: 'indev'\nclass Main extends Object:\n    method main():\n        return this is Missing\n    end\nend\n";
+    let vm = Vm::builder().source("main.adv", source).build().unwrap();
+    let warnings = vm.warnings().iter().map(|w| (w.span.line, w.message.as_str())).collect::<Vec<_>>();
+    assert_eq!(warnings, [(4, "Couldn't find a class named 'Missing', 'is' check will be ignored")]);
+}
+
+#[test]
+fn unknown_names_dont_get_interned() {
+    let mut vm = Vm::builder().source("main.adv", SOURCE).build().unwrap();
+    let main = vm.entrypoint();
+    assert!(matches!(vm.instantiate("NeverDefinedClass"), Err(AdvError::UnknownClass { name }) if name == "NeverDefinedClass"));
+    assert!(matches!(vm.call(&main, "neverDefinedMethod", &[]), Err(AdvError::UnknownMethod { method, .. }) if method == "neverDefinedMethod"));
+    let error = vm.field(&main, "neverDefinedField").unwrap_err();
+    assert!(matches!(&error, AdvError::Runtime(e) if matches!(&e.kind, RuntimeErrorKind::UndefinedField { field, .. } if field == "neverDefinedField")), "{error:?}");
+
+    let error = Vm::builder().source("main.adv", SOURCE).entrypoint("NeverDefinedEntrypoint").build().err().unwrap();
+    assert!(matches!(&error, AdvError::UnknownClass { name } if name == "NeverDefinedEntrypoint"), "{error:?}");
+
+    for name in ["NeverDefinedClass", "neverDefinedMethod", "neverDefinedField", "NeverDefinedEntrypoint"] {
+        assert_eq!(Symbol::lookup(name), None);
+    }
+}